@@ -0,0 +1,484 @@
+use crate::{AssetData, error::Error, u256_division};
+use alloy::primitives::{Address, U256, U512};
+use clap::ValueEnum;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Fixed point used to turn the share based strategies into apportionment scores.
+const SHARE_SCALE: f64 = 1e18;
+
+/// Decides how the voting pool is split between the assets that pass the filters.
+pub trait AllocationStrategy {
+    fn name(&self) -> String;
+
+    /// Scores the voting pool is apportioned over, one per asset in input order. Only the
+    /// ratios matter, the u16 conversion happens in `largest_remainder`. Every asset has a
+    /// `weight`, `calculate_actual_weights` checks it before scoring.
+    fn scores(&self, assets: &[AssetData]) -> Vec<U256>;
+}
+
+/// The original rule: the pool follows the raw vote weights.
+pub struct Proportional;
+
+impl AllocationStrategy for Proportional {
+    fn name(&self) -> String {
+        "proportional".to_owned()
+    }
+
+    fn scores(&self, assets: &[AssetData]) -> Vec<U256> {
+        assets
+            .iter()
+            .map(|x| x.weight.unwrap_or_default())
+            .collect()
+    }
+}
+
+/// Proportional, but every asset gets at least `floor` of the pool. The caps are not a
+/// strategy, `max_weight` and `[[caps]]` apply to all of them.
+pub struct Floored {
+    pub floor: f64,
+}
+
+impl AllocationStrategy for Floored {
+    fn name(&self) -> String {
+        format!("floored {:.2}%", self.floor * 100.)
+    }
+
+    fn scores(&self, assets: &[AssetData]) -> Vec<U256> {
+        let shares = vote_shares(assets);
+        shares_to_scores(&apply_floor(&shares, self.floor))
+    }
+}
+
+/// Square root of the vote weights, dampens the lead of the most voted assets.
+pub struct SqrtDampened;
+
+impl AllocationStrategy for SqrtDampened {
+    fn name(&self) -> String {
+        "sqrt".to_owned()
+    }
+
+    fn scores(&self, assets: &[AssetData]) -> Vec<U256> {
+        assets
+            .iter()
+            .map(|x| x.weight.unwrap_or_default().root(2))
+            .collect()
+    }
+}
+
+/// Mixes the vote shares with a market cap weighted baseline, `vote_share` of the pool
+/// follows the vote. Assets without a market cap get nothing from the baseline.
+pub struct MarketCapBlend {
+    pub vote_share: f64,
+    pub market_caps: HashMap<Address, f64>,
+}
+
+impl AllocationStrategy for MarketCapBlend {
+    fn name(&self) -> String {
+        format!("blend {:.0}% vote", self.vote_share * 100.)
+    }
+
+    fn scores(&self, assets: &[AssetData]) -> Vec<U256> {
+        let votes = vote_shares(assets);
+        let caps: Vec<f64> = assets
+            .iter()
+            .map(|x| self.market_caps.get(&x.token_addr).copied().unwrap_or(0.))
+            .collect();
+        let caps_sum: f64 = caps.iter().sum();
+        if caps_sum <= 0. {
+            return shares_to_scores(&votes);
+        }
+        let blended: Vec<f64> = votes
+            .iter()
+            .zip(caps)
+            .map(|(vote, cap)| self.vote_share * vote + (1. - self.vote_share) * cap / caps_sum)
+            .collect();
+        shares_to_scores(&blended)
+    }
+}
+
+/// The built-in strategies, see [`build_strategy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StrategyKind {
+    /// Follow the vote weights, the original rule
+    Proportional,
+    /// Follow the vote weights with at least --floor of the pool per asset
+    Floored,
+    /// Follow the square root of the vote weights
+    Sqrt,
+    /// Blend the vote with the market caps in --market-caps
+    Blend,
+}
+
+/// Parameters of the strategies, each one only reads its own.
+#[derive(Debug, Clone)]
+pub struct StrategyOptions {
+    /// Smallest share of the voting pool an asset can get, for `floored`
+    pub floor: f64,
+    /// Share of the voting pool that follows the vote, for `blend`
+    pub vote_share: f64,
+    /// CSV of `token,market_cap` lines, for `blend`
+    pub market_caps: Option<PathBuf>,
+}
+
+/// Builds the strategy of the given kind.
+pub fn build_strategy(
+    kind: StrategyKind,
+    args: &StrategyOptions,
+) -> Result<Box<dyn AllocationStrategy>, Error> {
+    Ok(match kind {
+        StrategyKind::Proportional => Box::new(Proportional),
+        StrategyKind::Floored => Box::new(Floored { floor: args.floor }),
+        StrategyKind::Sqrt => Box::new(SqrtDampened),
+        StrategyKind::Blend => {
+            let path = args
+                .market_caps
+                .as_deref()
+                .ok_or_else(|| Error::config("The blend strategy needs --market-caps"))?;
+            Box::new(MarketCapBlend {
+                vote_share: args.vote_share,
+                market_caps: load_market_caps(path)?,
+            })
+        }
+    })
+}
+
+/// Reads `token,market_cap` lines, a header line and `#` comments are skipped.
+pub fn load_market_caps(path: &Path) -> Result<HashMap<Address, f64>, Error> {
+    let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let invalid = |line: usize, reason: &str| {
+        Error::decode(
+            format!("market caps {}:{}", path.display(), line + 1),
+            reason,
+        )
+    };
+    let mut out = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with("token") {
+            continue;
+        }
+        let (token, market_cap) = line
+            .split_once(',')
+            .ok_or_else(|| invalid(i, "expected token,market_cap"))?;
+        let token: Address = token
+            .trim()
+            .parse()
+            .map_err(|_| invalid(i, "invalid token address"))?;
+        let market_cap: f64 = market_cap
+            .trim()
+            .parse()
+            .map_err(|_| invalid(i, "invalid market cap"))?;
+        out.insert(token, market_cap);
+    }
+    Ok(out)
+}
+
+fn vote_shares(assets: &[AssetData]) -> Vec<f64> {
+    let total = assets
+        .iter()
+        .fold(U256::ZERO, |acc, x| acc + x.weight.unwrap_or_default());
+    if total.is_zero() {
+        return vec![1. / assets.len() as f64; assets.len()];
+    }
+    assets
+        .iter()
+        .map(|x| u256_division(&x.weight.unwrap_or_default(), &total).unwrap_or_default())
+        .collect()
+}
+
+fn shares_to_scores(shares: &[f64]) -> Vec<U256> {
+    shares
+        .iter()
+        .map(|x| U256::from((x * SHARE_SCALE) as u128))
+        .collect()
+}
+
+/// Raises the `shares` (summing to 1) below `floor` to it and takes the difference from the
+/// others, proportionally to their share, until none is below. A floor no split can meet is
+/// relaxed to an even split.
+fn apply_floor(shares: &[f64], floor: f64) -> Vec<f64> {
+    let floor = floor.min(1. / shares.len() as f64);
+    let mut fixed = vec![false; shares.len()];
+    loop {
+        let fixed_sum = floor * fixed.iter().filter(|x| **x).count() as f64;
+        let free_sum: f64 = shares
+            .iter()
+            .zip(fixed.iter())
+            .filter(|(_, fixed)| !**fixed)
+            .map(|(share, _)| share)
+            .sum();
+        let free_count = fixed.iter().filter(|x| !**x).count();
+        let scaled = |share: f64| match free_sum > 0. {
+            true => share / free_sum * (1. - fixed_sum),
+            false => (1. - fixed_sum) / free_count as f64,
+        };
+
+        let under: Vec<usize> = (0..shares.len())
+            .filter(|i| !fixed[*i] && scaled(shares[*i]) < floor)
+            .collect();
+        if under.is_empty() {
+            return shares
+                .iter()
+                .zip(fixed)
+                .map(|(share, fixed)| match fixed {
+                    true => floor,
+                    false => scaled(*share),
+                })
+                .collect();
+        }
+        under.iter().for_each(|i| fixed[*i] = true);
+    }
+}
+
+/// Prints the u16 allocation of every strategy next to each other.
+pub fn print_comparison(names: &[String], allocations: &[Vec<AssetData>]) {
+    let mut header = format!("{:<12}", "Asset");
+    for name in names.iter() {
+        header.push_str(&format!(" {:>22}", name));
+    }
+    println!("{}", header);
+
+    let by_id: Vec<HashMap<U256, &AssetData>> = allocations
+        .iter()
+        .map(|x| x.iter().map(|asset| (asset.id, asset)).collect())
+        .collect();
+    let mut seen = Vec::new();
+    for asset in allocations.iter().flatten() {
+        if seen.contains(&asset.id) {
+            continue;
+        }
+        seen.push(asset.id);
+        let mut line = format!("{:<12}", asset.report_symbol());
+        for allocation in by_id.iter() {
+            let cell = match allocation.get(&asset.id) {
+                Some(x) => format!(
+                    "{:>6} ({:>6.2}%)",
+                    x.converted_weight.unwrap_or_default(),
+                    x.actual_weight.unwrap_or_default() * 100.
+                ),
+                None => "-".to_owned(),
+            };
+            line.push_str(&format!(" {:>22}", cell));
+        }
+        println!("{}", line);
+    }
+}
+
+/// Splits `seats` units between `weights` with the largest remainder (Hamilton) method.
+///
+/// Every entry first gets the floor of its exact quota `weight * seats / total`, computed in
+/// U512 so nothing is lost to rounding. The units left over, fewer than the number of
+/// entries, go one each to the largest remainders. Ties between equal remainders are broken by
+/// the lower id, so the result does not depend on the input order.
+/// Returns the allocation in input order, or `None` when the weights sum to zero.
+pub fn largest_remainder(weights: &[(U256, U256)], seats: u16) -> Option<Vec<u16>> {
+    let total = weights
+        .iter()
+        .fold(U512::ZERO, |acc, (_, weight)| acc + weight.to::<U512>());
+    if total.is_zero() {
+        return None;
+    }
+
+    let seats_wide = U512::from(seats);
+    let mut out = Vec::with_capacity(weights.len());
+    let mut remainders = Vec::with_capacity(weights.len());
+    for (i, (id, weight)) in weights.iter().enumerate() {
+        let (quota, remainder) = (weight.to::<U512>() * seats_wide).div_rem(total);
+        out.push(quota.to::<u16>());
+        remainders.push((remainder, *id, i));
+    }
+
+    let assigned: u32 = out.iter().map(|x| *x as u32).sum();
+    let leftover = seats as u32 - assigned;
+    remainders.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, _, i) in remainders.iter().take(leftover as usize) {
+        out[*i] += 1;
+    }
+    Some(out)
+}
+
+/// `largest_remainder` with a maximum per entry. Entries allotted more than their cap are
+/// pinned to it and the seats left are apportioned again over the others, proportionally to
+/// their weight, until no entry is over its cap. Returns `None` when the weights sum to zero
+/// or the caps add up to less than `seats`.
+pub fn capped_largest_remainder(
+    weights: &[(U256, U256)],
+    caps: &[u16],
+    seats: u16,
+) -> Option<Vec<u16>> {
+    let caps_sum: u32 = caps.iter().map(|x| *x as u32).sum();
+    if caps_sum < seats as u32 {
+        return None;
+    }
+
+    let mut pinned: Vec<Option<u16>> = vec![None; weights.len()];
+    loop {
+        let free: Vec<usize> = (0..weights.len())
+            .filter(|i| pinned[*i].is_none())
+            .collect();
+        let left = seats - pinned.iter().flatten().sum::<u16>();
+        let out = match free.is_empty() {
+            true => Vec::new(),
+            false => {
+                let free_weights: Vec<(U256, U256)> = free.iter().map(|i| weights[*i]).collect();
+                largest_remainder(&free_weights, left)?
+            }
+        };
+
+        let over: Vec<usize> = free
+            .iter()
+            .zip(out.iter())
+            .filter(|(i, seats)| **seats > caps[**i])
+            .map(|(i, _)| *i)
+            .collect();
+        if over.is_empty() {
+            for (i, seats) in free.iter().zip(out) {
+                pinned[*i] = Some(seats);
+            }
+            return Some(pinned.into_iter().flatten().collect());
+        }
+        for i in over {
+            pinned[i] = Some(caps[i]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn weights() -> impl Strategy<Value = Vec<(U256, U256)>> {
+        prop::collection::vec(any::<[u8; 32]>(), 1..40).prop_map(|raw| {
+            raw.into_iter()
+                .enumerate()
+                // Mix tiny and huge weights, the top byte decides the magnitude
+                .map(|(i, x)| {
+                    let weight = U256::from_be_bytes(x) >> x[0] as usize;
+                    (U256::from(i), weight)
+                })
+                .collect()
+        })
+    }
+
+    #[test]
+    fn splits_evenly_by_id() {
+        let weights: Vec<(U256, U256)> = (0..3u8).map(|i| (U256::from(i), U256::from(1))).collect();
+        // 65535 = 3 * 21845, then 7 = 3 * 2 + 1 leftover for the lowest id
+        assert_eq!(largest_remainder(&weights, u16::MAX), Some(vec![21845; 3]));
+        assert_eq!(largest_remainder(&weights, 7), Some(vec![3, 2, 2]));
+        assert_eq!(largest_remainder(&[(U256::ZERO, U256::ZERO)], 7), None);
+
+        // Quotas 0.5 and 1.5 tie on the remainder despite different weights
+        let weights = [
+            (U256::from(2), U256::from(1)),
+            (U256::from(1), U256::from(3)),
+        ];
+        assert_eq!(largest_remainder(&weights, 2), Some(vec![0, 2]));
+    }
+
+    #[test]
+    fn redistributes_over_cap() {
+        let weights: Vec<(U256, U256)> = [70u8, 20, 10]
+            .iter()
+            .enumerate()
+            .map(|(i, x)| (U256::from(i), U256::from(*x)))
+            .collect();
+        // The first asset is pinned at 40, its excess is split 2:1 over the others
+        assert_eq!(
+            capped_largest_remainder(&weights, &[40, 100, 100], 100),
+            Some(vec![40, 40, 20])
+        );
+        assert_eq!(capped_largest_remainder(&weights, &[30, 30, 30], 100), None);
+    }
+
+    proptest! {
+        #[test]
+        fn sums_to_seats(weights in weights(), seats in any::<u16>()) {
+            if let Some(out) = largest_remainder(&weights, seats) {
+                prop_assert_eq!(out.iter().map(|x| *x as u32).sum::<u32>(), seats as u32);
+            }
+        }
+
+        #[test]
+        fn stays_within_one_of_quota(weights in weights()) {
+            let Some(out) = largest_remainder(&weights, u16::MAX) else {
+                return Ok(());
+            };
+            let total = weights.iter().fold(U512::ZERO, |acc, x| acc + x.1.to::<U512>());
+            for ((_, weight), seats) in weights.iter().zip(out) {
+                let quota = weight.to::<U512>() * U512::from(u16::MAX);
+                let seats = U512::from(seats) * total;
+                prop_assert!(seats <= quota + total && quota < seats + total);
+            }
+        }
+
+        #[test]
+        fn ignores_input_order(weights in weights(), seed in any::<u64>()) {
+            let mut shuffled: Vec<(usize, (U256, U256))> =
+                weights.iter().copied().enumerate().collect();
+            shuffled
+                .sort_unstable_by_key(|(i, _)| (*i as u64).wrapping_mul(seed | 1).rotate_left(17));
+            let reordered: Vec<(U256, U256)> = shuffled.iter().map(|x| x.1).collect();
+
+            let out = largest_remainder(&weights, u16::MAX);
+            let out_reordered = largest_remainder(&reordered, u16::MAX);
+            if let (Some(out), Some(out_reordered)) = (out, out_reordered) {
+                for ((i, _), seats) in shuffled.iter().zip(out_reordered) {
+                    prop_assert_eq!(out[*i], seats);
+                }
+            }
+        }
+
+        #[test]
+        fn larger_weight_never_gets_less(weights in weights()) {
+            if let Some(out) = largest_remainder(&weights, u16::MAX) {
+                for (a, seats_a) in weights.iter().zip(out.iter()) {
+                    for (b, seats_b) in weights.iter().zip(out.iter()) {
+                        if a.1 > b.1 {
+                            prop_assert!(seats_a >= seats_b);
+                        }
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn capped_sums_to_seats_within_caps(weights in weights(), cap in 2000u16..u16::MAX) {
+            let caps = vec![cap; weights.len()];
+            let out = capped_largest_remainder(&weights, &caps, u16::MAX);
+            if (cap as usize) * weights.len() < u16::MAX as usize {
+                prop_assert!(out.is_none());
+            } else if let Some(out) = out {
+                prop_assert_eq!(out.iter().map(|x| *x as u32).sum::<u32>(), u16::MAX as u32);
+                prop_assert!(out.iter().all(|x| *x <= cap));
+            }
+        }
+
+        #[test]
+        fn loose_caps_change_nothing(weights in weights()) {
+            let caps = vec![u16::MAX; weights.len()];
+            prop_assert_eq!(
+                capped_largest_remainder(&weights, &caps, u16::MAX),
+                largest_remainder(&weights, u16::MAX)
+            );
+        }
+
+        #[test]
+        fn floors_hold(raw in prop::collection::vec(1u32..1_000_000, 5..30)) {
+            let total: f64 = raw.iter().map(|x| *x as f64).sum();
+            let shares: Vec<f64> = raw.iter().map(|x| *x as f64 / total).collect();
+            let out = apply_floor(&shares, 0.01);
+            prop_assert!((out.iter().sum::<f64>() - 1.).abs() < 1e-9);
+            for (share, before) in out.iter().zip(shares.iter()) {
+                prop_assert!(*share >= 0.01 - 1e-9);
+                // Only the assets under the floor gain
+                prop_assert!(*before < 0.01 || *share <= *before + 1e-9);
+            }
+        }
+    }
+}
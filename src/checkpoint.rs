@@ -0,0 +1,541 @@
+use crate::{
+    IJooceVoting::IJooceVotingErrors,
+    VotingContract,
+    error::{BoxError, Error},
+    fetch_data::{VOTING_CHAIN, get_asset_updated_at},
+};
+use alloy::{
+    network::{Network, ReceiptResponse, TransactionBuilder},
+    primitives::{Address, TxHash, U256, utils::format_ether},
+    providers::Provider,
+};
+use chrono::Utc;
+use op_alloy_network::Optimism;
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+#[derive(Debug, Clone)]
+pub struct CheckpointSimulation {
+    pub id: U256,
+    pub gas: Option<u64>,
+    pub revert: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Staleness {
+    /// The asset has never been checkpointed
+    Never,
+    /// The asset lags behind the last checkpoint of the weights sum
+    BehindSum { updated_at: U256, time_sum: U256 },
+    /// The asset was last checkpointed longer ago than the threshold
+    TooOld { updated_at: U256, age: U256 },
+    /// The asset is current, checkpointing it would only waste gas
+    Current { updated_at: U256 },
+}
+
+impl Staleness {
+    pub fn is_stale(&self) -> bool {
+        !matches!(self, Staleness::Current { .. })
+    }
+}
+
+impl std::fmt::Display for Staleness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Staleness::Never => write!(f, "never checkpointed"),
+            Staleness::BehindSum {
+                updated_at,
+                time_sum,
+            } => write!(f, "updated at {} behind timeSum {}", updated_at, time_sum),
+            Staleness::TooOld { updated_at, age } => {
+                write!(f, "updated at {}, {}s ago", updated_at, age)
+            }
+            Staleness::Current { updated_at } => write!(f, "current, updated at {}", updated_at),
+        }
+    }
+}
+
+/// Classifies an asset from its `assetUpdatedAt`. An asset is stale when it was never
+/// checkpointed, when it lags behind `timeSum`/`lastTime`, or when it is older than `max_age`.
+pub fn classify_staleness(
+    updated_at: U256,
+    last_time: U256,
+    time_sum: U256,
+    now: U256,
+    max_age: Option<U256>,
+) -> Staleness {
+    let reference = time_sum.max(last_time);
+    if updated_at.is_zero() {
+        Staleness::Never
+    } else if updated_at < reference {
+        Staleness::BehindSum {
+            updated_at,
+            time_sum: reference,
+        }
+    } else if let Some(max_age) = max_age
+        && now.saturating_sub(updated_at) >= max_age
+    {
+        Staleness::TooOld {
+            updated_at,
+            age: now.saturating_sub(updated_at),
+        }
+    } else {
+        Staleness::Current { updated_at }
+    }
+}
+
+/// Reads `assetUpdatedAt` for every id and keeps the stale ones, printing why each asset
+/// is checkpointed or skipped.
+pub async fn select_stale_assets(
+    contract: &VotingContract,
+    ids: &[U256],
+    max_age: Option<u64>,
+) -> Result<Vec<U256>, Error> {
+    let (updated_at, last_time, time_sum) =
+        get_asset_updated_at(contract.provider().clone(), contract, ids).await?;
+    let now = U256::from(Utc::now().timestamp());
+
+    let mut stale = Vec::new();
+    let mut skipped = 0;
+    for (id, updated_at) in ids.iter().zip(updated_at) {
+        let staleness = classify_staleness(
+            updated_at,
+            last_time,
+            time_sum,
+            now,
+            max_age.map(U256::from),
+        );
+        if staleness.is_stale() {
+            println!("checkpoint {} - {}", id, staleness);
+            stale.push(*id);
+        } else {
+            println!("skip       {} - {}", id, staleness);
+            skipped += 1;
+        }
+    }
+    println!(
+        "{} stale, {} skipped (lastTime {}, timeSum {})",
+        stale.len(),
+        skipped,
+        last_time,
+        time_sum
+    );
+    Ok(stale)
+}
+
+/// Simulates `checkpointAsset` for every id with `eth_call` and `eth_estimateGas`,
+/// sending from `from` so authorisation checks behave like the real transaction.
+pub async fn simulate_checkpoints(
+    contract: &VotingContract,
+    from: Address,
+    ids: &[U256],
+) -> Vec<CheckpointSimulation> {
+    let simulations = ids.iter().map(|id| async move {
+        let call = contract.checkpointAsset(*id).from(from);
+        let (result, gas) = tokio::join!(call.call(), call.estimate_gas());
+        match result.and(gas) {
+            Ok(gas) => CheckpointSimulation {
+                id: *id,
+                gas: Some(gas),
+                revert: None,
+            },
+            Err(e) => CheckpointSimulation {
+                id: *id,
+                gas: None,
+                revert: Some(decode_revert(&e)),
+            },
+        }
+    });
+    futures::future::join_all(simulations).await
+}
+
+/// Turns a failed call into the name of the `IJooceVoting` custom error when possible.
+pub fn decode_revert(error: &alloy::contract::Error) -> String {
+    if let Some(decoded) = error.as_decoded_interface_error::<IJooceVotingErrors>() {
+        // Every custom error on the contract is parameterless, so the variant name says it all
+        let name = format!("{:?}", decoded);
+        return name.split('(').next().unwrap_or(&name).to_owned();
+    }
+    match error.as_revert_data() {
+        Some(data) if !data.is_empty() => format!("reverted with unknown data {}", data),
+        _ => error.to_string(),
+    }
+}
+
+/// Prints the expected gas and fee of every checkpoint at the current gas price.
+pub async fn print_simulation_report(
+    contract: &VotingContract,
+    simulations: &[CheckpointSimulation],
+) -> Result<(), Error> {
+    let gas_price = contract
+        .provider()
+        .get_gas_price()
+        .await
+        .map_err(|e| Error::rpc(VOTING_CHAIN, "eth_gasPrice", e))?;
+    let mut total_gas = 0u64;
+
+    println!("{:<80} {:>10} {:>22}", "Asset id", "Gas", "Fee (ETH)");
+    for simulation in simulations.iter() {
+        match (simulation.gas, &simulation.revert) {
+            (Some(gas), _) => {
+                total_gas += gas;
+                let fee = U256::from(gas) * U256::from(gas_price);
+                println!(
+                    "{:<80} {:>10} {:>22}",
+                    simulation.id,
+                    gas,
+                    format_ether(fee)
+                );
+            }
+            (None, revert) => {
+                println!(
+                    "{:<80} {:>10} {:>22}",
+                    simulation.id,
+                    "-",
+                    format!("REVERT {}", revert.as_deref().unwrap_or("unknown"))
+                );
+            }
+        }
+    }
+    let total_fee = U256::from(total_gas) * U256::from(gas_price);
+    let reverted = simulations.iter().filter(|x| x.revert.is_some()).count();
+    println!(
+        "{} checkpoints, {} would revert, total gas {} at {} gwei = {} ETH",
+        simulations.len(),
+        reverted,
+        total_gas,
+        gas_price as f64 / 1e9,
+        format_ether(total_fee)
+    );
+    Ok(())
+}
+
+/// Asks the operator to confirm on stdin, anything but `y`/`yes` aborts.
+pub fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N] ", prompt);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubmitOptions {
+    /// How long to wait for a receipt before the transaction is considered stuck
+    pub receipt_timeout: Duration,
+    /// How many times a stuck or underpriced transaction is replaced
+    pub max_bumps: u32,
+    /// Fee increase per replacement, nodes require at least 10%
+    pub bump_percent: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum CheckpointOutcome {
+    Success {
+        tx_hash: TxHash,
+        block_number: Option<u64>,
+        gas_used: u64,
+        bumps: u32,
+    },
+    Reverted {
+        tx_hash: TxHash,
+        bumps: u32,
+    },
+    Dropped {
+        reason: String,
+        tx_hashes: Vec<TxHash>,
+        /// Zero-value self transfer that took the unused nonce, so the later checkpoints
+        /// aren't stuck behind the gap
+        filled_by: Option<TxHash>,
+    },
+}
+
+/// Sends one `checkpointAsset` per `(asset id, estimated gas)` pair from `from`, the address
+/// of the provider's wallet. Nonces are assigned locally from its pending nonce so every
+/// transaction is in flight at once, receipts are awaited concurrently and stuck or
+/// underpriced transactions are replaced with bumped fees. The nonce of a dropped checkpoint
+/// is filled with a zero-value self transfer so the ones after it can still be mined.
+pub async fn submit_checkpoints(
+    contract: &VotingContract,
+    from: Address,
+    checkpoints: &[(U256, u64)],
+    options: SubmitOptions,
+) -> Result<Vec<(U256, CheckpointOutcome)>, Error> {
+    let provider = contract.provider();
+    let (base_nonce, fees) = tokio::try_join!(
+        provider.get_transaction_count(from).pending().into_future(),
+        provider.estimate_eip1559_fees()
+    )
+    .map_err(|e| Error::rpc(VOTING_CHAIN, format!("nonce and fees of {}", from), e))?;
+
+    let submissions = checkpoints
+        .iter()
+        .enumerate()
+        .map(|(i, (id, gas))| async move {
+            // Leave headroom over the estimate, state can move between simulation and inclusion
+            let gas_limit = gas + gas / 5;
+            let outcome = submit_checkpoint(
+                contract,
+                from,
+                *id,
+                base_nonce + i as u64,
+                gas_limit,
+                (fees.max_fee_per_gas, fees.max_priority_fee_per_gas),
+                options,
+            )
+            .await;
+            (*id, outcome)
+        });
+    Ok(futures::future::join_all(submissions).await)
+}
+
+async fn submit_checkpoint(
+    contract: &VotingContract,
+    from: Address,
+    id: U256,
+    nonce: u64,
+    gas_limit: u64,
+    (mut max_fee, mut priority_fee): (u128, u128),
+    options: SubmitOptions,
+) -> CheckpointOutcome {
+    let mut tx_hashes: Vec<TxHash> = Vec::new();
+    let mut reason = String::from("no transaction sent");
+
+    for bumps in 0..=options.max_bumps {
+        if bumps > 0 {
+            max_fee = bump_fee(max_fee, options.bump_percent);
+            priority_fee = bump_fee(priority_fee, options.bump_percent);
+        }
+        let sent = contract
+            .checkpointAsset(id)
+            .nonce(nonce)
+            .gas(gas_limit)
+            .max_fee_per_gas(max_fee)
+            .max_priority_fee_per_gas(priority_fee)
+            .send()
+            .await;
+
+        match sent {
+            Ok(pending) => {
+                tx_hashes.push(*pending.tx_hash());
+                match pending
+                    .with_timeout(Some(options.receipt_timeout))
+                    .get_receipt()
+                    .await
+                {
+                    Ok(receipt) => return receipt_outcome(&receipt, bumps),
+                    Err(e) => reason = e.to_string(),
+                }
+            }
+            Err(e) => {
+                let message = e.to_string().to_lowercase();
+                let retryable = message.contains("underpriced")
+                    || message.contains("fee too low")
+                    || message.contains("already known")
+                    // Only a replacement can hit this, an earlier send for the nonce was mined
+                    || (message.contains("nonce too low") && !tx_hashes.is_empty());
+                if !retryable {
+                    reason = e.to_string();
+                    break;
+                }
+                reason = e.to_string();
+            }
+        }
+
+        // One of the earlier transactions for this nonce may have been mined meanwhile
+        if let Some(outcome) = find_mined(contract, &tx_hashes, bumps).await {
+            return outcome;
+        }
+    }
+
+    let fees = (
+        bump_fee(max_fee, options.bump_percent),
+        bump_fee(priority_fee, options.bump_percent),
+    );
+    let filled_by = match fill_nonce(contract, from, nonce, fees, options).await {
+        Ok(val) => val,
+        Err(e) => {
+            reason = format!("{}, and filling nonce {} failed: {}", reason, nonce, e);
+            None
+        }
+    };
+    CheckpointOutcome::Dropped {
+        reason,
+        tx_hashes,
+        filled_by,
+    }
+}
+
+/// Takes `nonce` with a zero-value self transfer, replacing whatever is still pending at it,
+/// unless a transaction with that nonce was mined meanwhile.
+async fn fill_nonce(
+    contract: &VotingContract,
+    from: Address,
+    nonce: u64,
+    (max_fee, priority_fee): (u128, u128),
+    options: SubmitOptions,
+) -> Result<Option<TxHash>, Error> {
+    let provider = contract.provider();
+    let rpc = |call: &str, e: BoxError| {
+        Error::rpc(VOTING_CHAIN, format!("{} for nonce {}", call, nonce), e)
+    };
+    let mined = provider
+        .get_transaction_count(from)
+        .latest()
+        .await
+        .map_err(|e| rpc("eth_getTransactionCount", e.into()))?;
+    if mined > nonce {
+        return Ok(None);
+    }
+    let tx = <Optimism as Network>::TransactionRequest::default()
+        .with_from(from)
+        .with_to(from)
+        .with_value(U256::ZERO)
+        .with_nonce(nonce)
+        .with_gas_limit(21_000)
+        .with_max_fee_per_gas(max_fee)
+        .with_max_priority_fee_per_gas(priority_fee);
+    let receipt = provider
+        .send_transaction(tx)
+        .await
+        .map_err(|e| rpc("Sending a self transfer", e.into()))?
+        .with_timeout(Some(options.receipt_timeout))
+        .get_receipt()
+        .await
+        .map_err(|e| rpc("Waiting for the self transfer", e.into()))?;
+    Ok(Some(receipt.transaction_hash()))
+}
+
+async fn find_mined(
+    contract: &VotingContract,
+    tx_hashes: &[TxHash],
+    bumps: u32,
+) -> Option<CheckpointOutcome> {
+    for tx_hash in tx_hashes.iter() {
+        if let Ok(Some(receipt)) = contract.provider().get_transaction_receipt(*tx_hash).await {
+            return Some(receipt_outcome(&receipt, bumps));
+        }
+    }
+    None
+}
+
+fn receipt_outcome(receipt: &impl ReceiptResponse, bumps: u32) -> CheckpointOutcome {
+    if receipt.status() {
+        CheckpointOutcome::Success {
+            tx_hash: receipt.transaction_hash(),
+            block_number: receipt.block_number(),
+            gas_used: receipt.gas_used(),
+            bumps,
+        }
+    } else {
+        CheckpointOutcome::Reverted {
+            tx_hash: receipt.transaction_hash(),
+            bumps,
+        }
+    }
+}
+
+/// Raises a fee by `percent`, by at least one wei so a zero priority fee still moves.
+pub fn bump_fee(fee: u128, percent: u64) -> u128 {
+    (fee + fee * percent as u128 / 100).max(fee + 1)
+}
+
+pub fn print_submission_report(outcomes: &[(U256, CheckpointOutcome)]) {
+    let mut counts = [0usize; 3];
+    for (id, outcome) in outcomes.iter() {
+        match outcome {
+            CheckpointOutcome::Success {
+                tx_hash,
+                block_number,
+                gas_used,
+                bumps,
+            } => {
+                counts[0] += 1;
+                println!(
+                    "SUCCESS  {} {} block {} gas {} bumps {}",
+                    id,
+                    tx_hash,
+                    block_number.map(|x| x.to_string()).unwrap_or_default(),
+                    gas_used,
+                    bumps
+                );
+            }
+            CheckpointOutcome::Reverted { tx_hash, bumps } => {
+                counts[1] += 1;
+                println!("REVERTED {} {} bumps {}", id, tx_hash, bumps);
+            }
+            CheckpointOutcome::Dropped {
+                reason,
+                tx_hashes,
+                filled_by,
+            } => {
+                counts[2] += 1;
+                let last = tx_hashes.last().map(|x| x.to_string()).unwrap_or_default();
+                println!(
+                    "DROPPED  {} {} ({} sent) - {}",
+                    id,
+                    last,
+                    tx_hashes.len(),
+                    reason
+                );
+                if let Some(tx_hash) = filled_by {
+                    println!("         nonce filled by self transfer {}", tx_hash);
+                }
+            }
+        }
+    }
+    println!(
+        "{} succeeded, {} reverted, {} dropped",
+        counts[0], counts[1], counts[2]
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IJooceVoting::AssetNotAdded;
+    use alloy::{
+        hex,
+        sol_types::SolError,
+        transports::{RpcError, TransportErrorKind},
+    };
+
+    #[test]
+    fn classifies_staleness() {
+        let week = U256::from(604800);
+        let time_sum = week * U256::from(2900);
+        let now = time_sum + U256::from(1000);
+        assert_eq!(
+            classify_staleness(U256::ZERO, time_sum, time_sum, now, None),
+            Staleness::Never
+        );
+        assert!(classify_staleness(time_sum - week, time_sum, time_sum, now, None).is_stale());
+        assert!(!classify_staleness(time_sum, time_sum, time_sum, now, None).is_stale());
+        assert!(
+            classify_staleness(time_sum, time_sum, time_sum, now, Some(U256::from(500))).is_stale()
+        );
+    }
+
+    #[test]
+    fn bumps_fees() {
+        assert_eq!(bump_fee(1_000_000, 15), 1_150_000);
+        assert_eq!(bump_fee(0, 15), 1);
+    }
+
+    #[test]
+    fn decodes_custom_errors() {
+        let payload = serde_json::json!({
+            "code": 3,
+            "message": "execution reverted",
+            "data": hex::encode_prefixed(AssetNotAdded {}.abi_encode()),
+        });
+        let error = alloy::contract::Error::TransportError(
+            RpcError::<TransportErrorKind>::ErrorResp(serde_json::from_value(payload).unwrap()),
+        );
+        assert_eq!(decode_revert(&error), "AssetNotAdded");
+    }
+}
@@ -0,0 +1,305 @@
+use alloy::primitives::{Address, U256};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use jmx_weights::{
+    allocation::{StrategyKind, StrategyOptions},
+    fetch_data::At,
+    turnover::PreviousSource,
+};
+use std::path::PathBuf;
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Fetch Jooce voting weights and build the JMX rebalancing allocation"
+)]
+pub struct Cli {
+    /// Config file to load, defaults to the built-in profile
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Address of the Jooce voting contract, overrides the config
+    #[arg(long, global = true)]
+    pub voting_contract: Option<Address>,
+
+    /// Override the RPC url of a chain, e.g. `--rpc 8453=https://base.example`
+    #[arg(long = "rpc", global = true, value_name = "CHAIN_ID=URL", value_parser = parse_rpc_override)]
+    pub rpc_overrides: Vec<(U256, String)>,
+
+    /// Largest share of the index any asset can get, overrides max_weight in the config
+    #[arg(long, global = true, value_parser = parse_fraction)]
+    pub cap: Option<f64>,
+
+    /// How the voting pool is split between the assets that pass the filters
+    #[arg(long, global = true, value_enum, default_value_t = StrategyKind::Proportional)]
+    pub strategy: StrategyKind,
+
+    #[command(flatten)]
+    pub strategy_args: StrategyArgs,
+
+    /// Also print the raw fetched data and how long the run took
+    #[arg(long, short, global = true)]
+    pub verbose: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(flatten)]
+    Client(ClientCommand),
+    /// Simulate, confirm and send checkpoint transactions (requires PRIVATE_KEY)
+    Checkpoint {
+        /// Only checkpoint these asset ids, defaults to every asset
+        #[arg(long = "asset", value_name = "ASSET_ID")]
+        assets: Vec<U256>,
+        /// Checkpoint every asset, even the ones that are already current
+        #[arg(long)]
+        all: bool,
+        /// Also treat assets not checkpointed for this many seconds as stale
+        #[arg(long, value_name = "SECONDS")]
+        max_age: Option<u64>,
+        /// Only simulate the checkpoints, never send anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Send without asking for confirmation after the simulation
+        #[arg(long, short)]
+        yes: bool,
+        /// Simulate from this address instead of the PRIVATE_KEY one, only with --dry-run
+        #[arg(long, requires = "dry_run")]
+        from: Option<Address>,
+        /// Seconds to wait for a receipt before replacing the transaction
+        #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+        receipt_timeout: u64,
+        /// How many times a stuck or underpriced transaction is replaced
+        #[arg(long, default_value_t = 3)]
+        max_bumps: u32,
+        /// Fee increase in percent for each replacement
+        #[arg(long, default_value_t = 15, value_parser = clap::value_parser!(u64).range(10..))]
+        bump_percent: u64,
+    },
+    /// Show the most recent snapshot tabs from the Google Sheet
+    History {
+        /// Number of tabs to show, newest first
+        #[arg(long, default_value_t = 1)]
+        limit: usize,
+    },
+}
+
+/// Commands run on a `WeightClient` built from the config.
+#[derive(Debug, Subcommand)]
+pub enum ClientCommand {
+    /// Fetch the asset ids, raw weights and symbols from the voting contract
+    Fetch {
+        /// Rebuild the weights as of a past time: a unix timestamp, YYYY-MM-DD,
+        /// RFC 3339 date, or `block:<n>` to pin every read to a Base block
+        #[arg(long, value_name = "TIMESTAMP|block:N")]
+        at: Option<At>,
+    },
+    /// Compute the normalised allocation and print it
+    Compute {
+        /// Rebuild the weights as of a past time: a unix timestamp, YYYY-MM-DD,
+        /// RFC 3339 date, or `block:<n>` to pin every read to a Base block
+        #[arg(long, value_name = "TIMESTAMP|block:N")]
+        at: Option<At>,
+        #[command(flatten)]
+        turnover: TurnoverArgs,
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+    },
+    /// Compute the allocation and export it
+    Export {
+        /// Where to write the allocation
+        #[arg(long, value_enum, default_value_t = OutputTarget::All)]
+        output: OutputTarget,
+        /// Also write a Safe Transaction Builder batch setting the weights on the config's
+        /// [target] contract
+        #[arg(long, value_name = "PATH")]
+        safe_batch: Option<PathBuf>,
+        /// Also print the calldata setting the weights on the config's [target] contract, its
+        /// decoded arguments and the matching `cast` commands
+        #[arg(long)]
+        calldata: bool,
+        /// Rebuild the weights as of a past time: a unix timestamp, YYYY-MM-DD,
+        /// RFC 3339 date, or `block:<n>` to pin every read to a Base block
+        #[arg(long, value_name = "TIMESTAMP|block:N")]
+        at: Option<At>,
+        #[command(flatten)]
+        turnover: TurnoverArgs,
+        #[command(flatten)]
+        snapshot: SnapshotArgs,
+    },
+    /// Compute the allocation with several strategies and print them side by side
+    Compare {
+        /// Strategies to compare, comma separated
+        #[arg(long, value_enum, value_delimiter = ',', required = true)]
+        strategies: Vec<StrategyKind>,
+        /// Rebuild the weights as of a past time: a unix timestamp, YYYY-MM-DD,
+        /// RFC 3339 date, or `block:<n>` to pin every read to a Base block
+        #[arg(long, value_name = "TIMESTAMP|block:N")]
+        at: Option<At>,
+    },
+    /// Check that the config's [target] contract holds the expected weights, exits non-zero
+    /// on any difference
+    Verify {
+        /// Allocation to check: `sheet` for the newest tab, or a snapshot or CSV file.
+        /// Defaults to the newest snapshot in --snapshot-dir
+        #[arg(long, value_name = "sheet|PATH")]
+        expected: Option<PreviousSource>,
+        /// Directory the newest snapshot is taken from
+        #[arg(long, value_name = "PATH", default_value = "snapshots")]
+        snapshot_dir: PathBuf,
+    },
+    /// Export per-asset weights for every week in a date range as CSV
+    Timeseries {
+        /// First week to include: unix timestamp, YYYY-MM-DD or RFC 3339 date
+        #[arg(long, value_parser = parse_timestamp)]
+        from: u64,
+        /// Last week to include, defaults to now
+        #[arg(long, value_parser = parse_timestamp)]
+        to: Option<u64>,
+        /// Number of weeks between rows
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+        step_weeks: u64,
+        /// Write the CSV to this file instead of stdout
+        #[arg(long, value_name = "PATH")]
+        out: Option<PathBuf>,
+    },
+    /// Backfill Vote and AssetAdded events into a local SQLite database
+    Index {
+        /// SQLite database file, created if missing
+        #[arg(long, value_name = "PATH", default_value = "votes.sqlite")]
+        db: PathBuf,
+        /// Block to start from when the database is empty, defaults to `voting_deploy_block`
+        #[arg(long)]
+        from_block: Option<u64>,
+        /// Blocks per eth_getLogs request, halved when the RPC rejects a range
+        #[arg(long, default_value_t = 10_000)]
+        chunk_size: u64,
+        /// Blocks behind the head to stop at, to stay clear of reorgs
+        #[arg(long, default_value_t = 5)]
+        confirmations: u64,
+    },
+    /// List the indexed votes for an asset, newest first
+    Votes {
+        /// SQLite database file written by `index`
+        #[arg(long, value_name = "PATH", default_value = "votes.sqlite")]
+        db: PathBuf,
+        /// Asset id as a decimal or 0x-prefixed hex number
+        #[arg(long)]
+        asset: U256,
+        /// Number of votes to show
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Show which voters drive each asset's weight
+    Voters {
+        /// Voters to include, defaults to every voter in the index database
+        #[arg(long = "voter", value_name = "ADDRESS")]
+        voters: Vec<Address>,
+        /// SQLite database written by `index`, used to discover voters
+        #[arg(long, value_name = "PATH", default_value = "votes.sqlite")]
+        db: PathBuf,
+        /// Number of voters to show per asset
+        #[arg(long, default_value_t = 5)]
+        top: usize,
+    },
+    /// Project the allocation forward assuming no new votes
+    Forecast {
+        /// Number of weeks to project
+        #[arg(long, default_value_t = 12)]
+        weeks: u64,
+        /// Drift of any asset's share, as a fraction of the index, that calls for a rebalance
+        #[arg(long, default_value_t = 0.005, value_parser = parse_fraction)]
+        threshold: f64,
+    },
+    /// Decode a single asset id and show its current weight
+    InspectAsset {
+        /// Asset id as a decimal or 0x-prefixed hex number
+        asset_id: U256,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputTarget {
+    /// Append a new tab to the Google Sheet
+    Sheet,
+    /// Print the OFT address to u16 map
+    Stdout,
+    /// Both of the above
+    All,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct StrategyArgs {
+    /// Smallest share of the voting pool an asset can get, for `floored`
+    #[arg(long, global = true, default_value_t = 0., value_parser = parse_fraction)]
+    pub floor: f64,
+    /// Share of the voting pool that follows the vote, for `blend`
+    #[arg(long, global = true, default_value_t = 0.5, value_parser = parse_fraction)]
+    pub vote_share: f64,
+    /// CSV of `token,market_cap` lines, for `blend`
+    #[arg(long, global = true, value_name = "PATH")]
+    pub market_caps: Option<PathBuf>,
+}
+
+impl StrategyArgs {
+    pub fn options(&self) -> StrategyOptions {
+        StrategyOptions {
+            floor: self.floor,
+            vote_share: self.vote_share,
+            market_caps: self.market_caps.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct TurnoverArgs {
+    /// Previous allocation to damp changes against: `sheet` for the newest tab, or a CSV
+    /// file with `oft_address` and `uint16` columns
+    #[arg(long, value_name = "sheet|PATH")]
+    pub previous: Option<PreviousSource>,
+    /// Assets that moved by less than this share of the index keep their previous weight
+    #[arg(long, default_value_t = 0.001, value_parser = parse_fraction, requires = "previous")]
+    pub min_change: f64,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SnapshotArgs {
+    /// Directory the JSON and CSV snapshot of the run is written to
+    #[arg(long, value_name = "PATH", default_value = "snapshots")]
+    pub snapshot_dir: PathBuf,
+    /// Don't write a snapshot
+    #[arg(long)]
+    pub no_snapshot: bool,
+}
+
+fn parse_rpc_override(value: &str) -> Result<(U256, String), String> {
+    let (chain_id, url) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected CHAIN_ID=URL, got `{value}`"))?;
+    let chain_id = chain_id
+        .trim()
+        .parse::<U256>()
+        .map_err(|e| format!("invalid chain id `{chain_id}`: {e}"))?;
+    Ok((chain_id, url.trim().to_owned()))
+}
+
+fn parse_timestamp(value: &str) -> Result<u64, String> {
+    match value.parse::<At>()? {
+        At::Timestamp(timestamp) => Ok(timestamp),
+        At::Block(_) => Err("expected a date or timestamp, not a block".to_owned()),
+    }
+}
+
+fn parse_fraction(value: &str) -> Result<f64, String> {
+    let fraction = value
+        .parse::<f64>()
+        .map_err(|e| format!("invalid number `{value}`: {e}"))?;
+    if !(0. ..=1.).contains(&fraction) {
+        return Err(format!(
+            "expected a fraction between 0 and 1, got {fraction}"
+        ));
+    }
+    Ok(fraction)
+}
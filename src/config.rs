@@ -0,0 +1,663 @@
+use crate::{error::Error, variables::SOLANA_CHAIN_ID};
+use alloy::{
+    primitives::{Address, B256, U256, keccak256},
+    transports::http::reqwest::Url,
+};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::{HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Schema version understood by this build.
+pub const CONFIG_VERSION: u32 = 2;
+/// Default profile, compiled into the binary.
+pub const DEFAULT_CONFIG: &str = include_str!("../config/default.toml");
+
+/// Read before the rest, so an older file gets the version error rather than one about
+/// the keys it still has.
+#[derive(Debug, Deserialize)]
+struct RawVersion {
+    version: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    /// Checked through `RawVersion`, only listed so the key is not unknown
+    #[allow(dead_code)]
+    version: u32,
+    voting_contract: Address,
+    voting_chain_id: u64,
+    voting_deploy_block: Option<u64>,
+    min_relative_weight: f64,
+    #[serde(default)]
+    reserved: Vec<RawReserved>,
+    #[serde(default = "default_max_weight")]
+    max_weight: f64,
+    #[serde(default)]
+    caps: Vec<RawCap>,
+    #[serde(default)]
+    inactive_assets: Vec<Address>,
+    chains: Vec<RawChain>,
+    #[serde(default)]
+    solana_mints: Vec<RawSolanaMint>,
+    #[serde(default)]
+    oft: Vec<RawOft>,
+    #[serde(default)]
+    symbols: Vec<RawSymbol>,
+    symbol_cache: Option<PathBuf>,
+    target: Option<RawTarget>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawChain {
+    id: u64,
+    name: String,
+    rpc_env: String,
+    #[serde(default)]
+    fallback_rpc_envs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawReserved {
+    symbol: String,
+    token: Address,
+    chain_id: u64,
+    share: Option<f64>,
+    weight: Option<u16>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCap {
+    token: Address,
+    max_weight: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSolanaMint {
+    token: Address,
+    mint: String,
+    placeholder: Option<Address>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawOft {
+    token: Address,
+    oft: Address,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSymbol {
+    token: Address,
+    symbol: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTarget {
+    address: Address,
+    chain_id: u64,
+    safe: Option<Address>,
+    abi: PathBuf,
+    method: String,
+    read_method: Option<String>,
+    list_method: Option<String>,
+    #[serde(default)]
+    order: WeightOrder,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chain {
+    pub name: String,
+    pub rpc_env: String,
+    pub rpc_url: Option<String>,
+    /// Urls of the `fallback_rpc_envs` that are set, tried in order when `rpc_url` fails
+    pub fallback_rpc_urls: Vec<String>,
+}
+
+/// Fixed allocation that is not voted on, e.g. JOOCE itself.
+#[derive(Debug, Clone)]
+pub struct ReservedSlot {
+    pub symbol: String,
+    pub token: Address,
+    pub chain_id: U256,
+    pub weight: u16,
+}
+
+/// Order the target contract expects the (address, u16) pairs in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightOrder {
+    /// Ascending OFT address
+    #[default]
+    Address,
+    /// Descending weight, ties by ascending address
+    Weight,
+}
+
+/// Index or vault contract the allocation is applied to.
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub address: Address,
+    pub chain_id: U256,
+    /// Safe the batch is proposed from, only recorded in the batch metadata
+    pub safe: Option<Address>,
+    /// JSON ABI of the contract, relative paths are resolved against the config file
+    pub abi: PathBuf,
+    /// Function that sets the weights
+    pub method: String,
+    /// View function returning the weight of an OFT address, for `verify`
+    pub read_method: Option<String>,
+    /// View function listing every address the contract holds a weight for, for `verify`
+    pub list_method: Option<String>,
+    pub order: WeightOrder,
+}
+
+/// Validated configuration, see `config/default.toml` for the meaning of each key.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// keccak256 of the TOML the config was loaded from
+    pub source_hash: B256,
+    pub voting_contract: Address,
+    pub voting_chain_id: U256,
+    /// Block the voting contract was deployed at, where `index` starts on an empty database
+    pub voting_deploy_block: Option<u64>,
+    pub min_relative_weight: f64,
+    pub reserved: Vec<ReservedSlot>,
+    /// Largest share of the index any asset can get
+    pub max_weight: f64,
+    /// Tighter per-asset caps, by token address
+    pub asset_caps: HashMap<Address, f64>,
+    pub inactive_assets: Vec<Address>,
+    pub chains: HashMap<U256, Chain>,
+    pub sol_mints: HashMap<Address, Pubkey>,
+    /// Token a Solana asset is listed under in the voting contract => its token address
+    pub sol_placeholders: HashMap<Address, Address>,
+    pub token_to_oft: HashMap<Address, Address>,
+    /// Symbols used when a token's chain can't be read, by token address
+    pub symbol_overrides: HashMap<Address, String>,
+    /// JSON file the last symbols read from each chain are kept in
+    pub symbol_cache: Option<PathBuf>,
+    pub target: Option<Target>,
+}
+
+impl Config {
+    /// Loads the config at `path`, or the built-in default profile when no path is given.
+    /// RPC urls are resolved from the environment, so `.env` must already be loaded.
+    pub fn load(path: Option<&Path>) -> Result<Config, Error> {
+        let invalid = |message: String, source: Error| Error::Config {
+            message,
+            source: Some(source.into()),
+        };
+        match path {
+            Some(path) => {
+                let contents = fs::read_to_string(path).map_err(|e| Error::Config {
+                    message: format!("Can't read config file {}", path.display()),
+                    source: Some(e.into()),
+                })?;
+                let mut config = Config::from_toml(&contents)
+                    .map_err(|e| invalid(format!("Invalid config file {}", path.display()), e))?;
+                // The ABI is written relative to the config file, not to where the tool runs
+                if let (Some(target), Some(dir)) = (config.target.as_mut(), path.parent()) {
+                    target.abi = dir.join(&target.abi);
+                }
+                Ok(config)
+            }
+            None => Config::from_toml(DEFAULT_CONFIG)
+                .map_err(|e| invalid("Invalid default config".to_owned(), e)),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Config, Error> {
+        let malformed = |e: toml::de::Error| Error::Config {
+            message: "Malformed TOML".to_owned(),
+            source: Some(e.into()),
+        };
+        let version = toml::from_str::<RawVersion>(contents)
+            .map_err(malformed)?
+            .version;
+        if version != CONFIG_VERSION {
+            return Err(Error::config(format!(
+                "unsupported config version {}, expected {}, see the README for the migration",
+                version, CONFIG_VERSION
+            )));
+        }
+        let raw: RawConfig = toml::from_str(contents).map_err(malformed)?;
+
+        let mut errors = Vec::new();
+
+        if !(0. ..1.).contains(&raw.min_relative_weight) {
+            errors.push(format!(
+                "min_relative_weight must be in [0, 1), got {}",
+                raw.min_relative_weight
+            ));
+        }
+
+        if !(0. ..=1.).contains(&raw.max_weight) {
+            errors.push(format!(
+                "max_weight must be in [0, 1], got {}",
+                raw.max_weight
+            ));
+        }
+        let mut asset_caps = HashMap::new();
+        for cap in raw.caps {
+            if !(0. ..=1.).contains(&cap.max_weight) {
+                errors.push(format!(
+                    "max_weight of {} must be in [0, 1], got {}",
+                    cap.token, cap.max_weight
+                ));
+            }
+            if asset_caps.insert(cap.token, cap.max_weight).is_some() {
+                errors.push(format!("duplicate caps token {}", cap.token));
+            }
+        }
+
+        let mut chains = HashMap::new();
+        for chain in raw.chains {
+            let id = U256::from(chain.id);
+            if chains.contains_key(&id) {
+                errors.push(format!("duplicate chain id {}", chain.id));
+                continue;
+            }
+            chains.insert(
+                id,
+                Chain {
+                    rpc_url: env::var(&chain.rpc_env).ok(),
+                    fallback_rpc_urls: chain
+                        .fallback_rpc_envs
+                        .iter()
+                        .filter_map(|x| env::var(x).ok())
+                        .collect(),
+                    name: chain.name,
+                    rpc_env: chain.rpc_env,
+                },
+            );
+        }
+        let voting_chain_id = U256::from(raw.voting_chain_id);
+        if !chains.contains_key(&voting_chain_id) {
+            errors.push(format!(
+                "voting_chain_id {} is not a known chain id, add it to [[chains]]",
+                raw.voting_chain_id
+            ));
+        }
+        if !raw.solana_mints.is_empty() && !chains.contains_key(&SOLANA_CHAIN_ID) {
+            errors.push(format!(
+                "solana_mints are configured but the Solana chain ({}) is not a known chain id",
+                SOLANA_CHAIN_ID
+            ));
+        }
+
+        let mut reserved = Vec::new();
+        let mut reserved_seen = HashSet::new();
+        let mut reserved_weight = 0u32;
+        for slot in raw.reserved {
+            let chain_id = U256::from(slot.chain_id);
+            if !chains.contains_key(&chain_id) {
+                errors.push(format!(
+                    "reserved slot {} is on unknown chain id {}",
+                    slot.symbol, slot.chain_id
+                ));
+            }
+            if !reserved_seen.insert((slot.token, chain_id)) {
+                errors.push(format!("duplicate reserved token {}", slot.token));
+            }
+            let weight = match (slot.share, slot.weight) {
+                (Some(share), None) if (0. ..1.).contains(&share) => {
+                    (share * u16::MAX as f64).round() as u16
+                }
+                (None, Some(weight)) => weight,
+                (Some(share), None) => {
+                    errors.push(format!(
+                        "share of reserved slot {} must be in [0, 1), got {}",
+                        slot.symbol, share
+                    ));
+                    continue;
+                }
+                _ => {
+                    errors.push(format!(
+                        "reserved slot {} needs exactly one of share or weight",
+                        slot.symbol
+                    ));
+                    continue;
+                }
+            };
+            reserved_weight += weight as u32;
+            reserved.push(ReservedSlot {
+                symbol: slot.symbol,
+                token: slot.token,
+                chain_id,
+                weight,
+            });
+        }
+        if reserved_weight >= u16::MAX as u32 {
+            errors.push(format!(
+                "reserved slots take {} of {}, leaving nothing for the vote",
+                reserved_weight,
+                u16::MAX
+            ));
+        }
+
+        let mut inactive_seen = HashSet::new();
+        for token in raw.inactive_assets.iter() {
+            if !inactive_seen.insert(token) {
+                errors.push(format!("duplicate inactive asset {}", token));
+            }
+        }
+
+        let mut sol_mints = HashMap::new();
+        let mut sol_placeholders = HashMap::new();
+        let mut mints_seen = HashSet::new();
+        for entry in raw.solana_mints.iter() {
+            if let Some(placeholder) = entry.placeholder
+                && sol_placeholders.insert(placeholder, entry.token).is_some()
+            {
+                errors.push(format!("duplicate Solana placeholder {}", placeholder));
+            }
+        }
+        for entry in raw.solana_mints {
+            if sol_placeholders.contains_key(&entry.token) {
+                errors.push(format!(
+                    "Solana token {} is also the placeholder of another one",
+                    entry.token
+                ));
+            }
+            let mint = match Pubkey::from_str(&entry.mint) {
+                Ok(val) => val,
+                Err(e) => {
+                    errors.push(format!(
+                        "malformed Solana mint `{}` for token {}: {}",
+                        entry.mint, entry.token, e
+                    ));
+                    continue;
+                }
+            };
+            if !mints_seen.insert(mint) {
+                errors.push(format!("duplicate Solana mint {}", mint));
+            }
+            if sol_mints.insert(entry.token, mint).is_some() {
+                errors.push(format!("duplicate solana_mints token {}", entry.token));
+            }
+        }
+
+        let mut token_to_oft = HashMap::new();
+        let mut ofts_seen = HashSet::new();
+        for entry in raw.oft {
+            if !ofts_seen.insert(entry.oft) {
+                errors.push(format!("duplicate OFT address {}", entry.oft));
+            }
+            if token_to_oft.insert(entry.token, entry.oft).is_some() {
+                errors.push(format!("duplicate oft token {}", entry.token));
+            }
+        }
+
+        let mut symbol_overrides = HashMap::new();
+        for entry in raw.symbols {
+            if entry.symbol.is_empty() {
+                errors.push(format!("empty symbol for token {}", entry.token));
+            }
+            if symbol_overrides.insert(entry.token, entry.symbol).is_some() {
+                errors.push(format!("duplicate symbols token {}", entry.token));
+            }
+        }
+
+        let target = raw.target.map(|target| Target {
+            address: target.address,
+            chain_id: U256::from(target.chain_id),
+            safe: target.safe,
+            abi: target.abi,
+            method: target.method,
+            read_method: target.read_method,
+            list_method: target.list_method,
+            order: target.order,
+        });
+        if let Some(target) = target.as_ref() {
+            if !chains.contains_key(&target.chain_id) {
+                errors.push(format!(
+                    "target chain_id {} is not a known chain id",
+                    target.chain_id
+                ));
+            }
+            if target.method.is_empty() {
+                errors.push("target method is empty".to_owned());
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(Error::config(errors.join("\n")));
+        }
+
+        Ok(Config {
+            source_hash: keccak256(contents),
+            voting_contract: raw.voting_contract,
+            voting_chain_id,
+            voting_deploy_block: raw.voting_deploy_block,
+            min_relative_weight: raw.min_relative_weight,
+            reserved,
+            max_weight: raw.max_weight,
+            asset_caps,
+            inactive_assets: raw.inactive_assets,
+            chains,
+            sol_mints,
+            sol_placeholders,
+            token_to_oft,
+            symbol_overrides,
+            symbol_cache: raw.symbol_cache,
+            target,
+        })
+    }
+
+    /// Replaces the RPC url of a chain, e.g. from `--rpc` on the command line.
+    pub fn override_rpc_url(&mut self, chain_id: U256, url: String) -> Result<(), Error> {
+        match self.chains.get_mut(&chain_id) {
+            Some(chain) => {
+                chain.rpc_url = Some(url);
+                Ok(())
+            }
+            None => Err(Error::UnknownChain { chain_id }),
+        }
+    }
+
+    /// Replaces `max_weight`, e.g. from `--cap` on the command line.
+    pub fn override_max_weight(&mut self, max_weight: f64) -> Result<(), Error> {
+        if !(0. ..=1.).contains(&max_weight) {
+            return Err(Error::config(format!(
+                "max_weight must be in [0, 1], got {}",
+                max_weight
+            )));
+        }
+        self.max_weight = max_weight;
+        Ok(())
+    }
+
+    pub fn rpc_url(&self, chain_id: &U256) -> Result<&str, Error> {
+        let chain = self.chains.get(chain_id).ok_or(Error::UnknownChain {
+            chain_id: *chain_id,
+        })?;
+        chain
+            .rpc_url
+            .as_deref()
+            .ok_or_else(|| Error::MissingRpcUrl {
+                chain: chain.name.clone(),
+                chain_id: *chain_id,
+                env: chain.rpc_env.clone(),
+            })
+    }
+
+    /// The RPC url of a chain, parsed for an HTTP provider.
+    pub fn rpc_endpoint(&self, chain_id: &U256) -> Result<Url, Error> {
+        Url::parse(self.rpc_url(chain_id)?).map_err(|e| Error::Config {
+            message: format!(
+                "Invalid RPC url for {} ({})",
+                self.chain_name(chain_id),
+                chain_id
+            ),
+            source: Some(e.into()),
+        })
+    }
+
+    /// Every usable RPC url of a chain, the primary one first and then the configured
+    /// fallbacks. Unset or invalid urls are skipped, the first of their errors is returned
+    /// when none is left.
+    pub fn rpc_endpoints(&self, chain_id: &U256) -> Result<Vec<Url>, Error> {
+        let chain = self.chains.get(chain_id).ok_or(Error::UnknownChain {
+            chain_id: *chain_id,
+        })?;
+        let mut first_error = None;
+        let mut out = Vec::new();
+        match self.rpc_endpoint(chain_id) {
+            Ok(url) => out.push(url),
+            Err(e) => first_error = Some(e),
+        }
+        for url in chain.fallback_rpc_urls.iter() {
+            match Url::parse(url) {
+                Ok(url) => out.push(url),
+                Err(e) => {
+                    first_error.get_or_insert(Error::Config {
+                        message: format!(
+                            "Invalid fallback RPC url for {} ({})",
+                            self.chain_name(chain_id),
+                            chain_id
+                        ),
+                        source: Some(e.into()),
+                    });
+                }
+            }
+        }
+        match (out.is_empty(), first_error) {
+            (true, Some(e)) => Err(e),
+            _ => Ok(out),
+        }
+    }
+
+    /// Solana mint of a token the voting contract lists under its placeholder address.
+    pub fn sol_mint(&self, token: &Address) -> Result<&Pubkey, Error> {
+        self.sol_mints
+            .get(token)
+            .ok_or(Error::UnmappedMint { token: *token })
+    }
+
+    /// u16 units taken by the reserved slots, the voting pool gets the rest.
+    pub fn reserved_weight(&self) -> u16 {
+        self.reserved.iter().map(|x| x.weight).sum()
+    }
+
+    pub fn is_reserved(&self, token: &Address, chain_id: &U256) -> bool {
+        self.reserved
+            .iter()
+            .any(|x| x.token == *token && x.chain_id == *chain_id)
+    }
+
+    /// Most u16 units a token can get, the tighter of `max_weight` and its own cap.
+    pub fn weight_cap(&self, token: &Address) -> u16 {
+        let max_weight = match self.asset_caps.get(token) {
+            Some(cap) => cap.min(self.max_weight),
+            None => self.max_weight,
+        };
+        (max_weight * u16::MAX as f64).floor() as u16
+    }
+
+    pub fn target(&self) -> Result<&Target, Error> {
+        self.target
+            .as_ref()
+            .ok_or_else(|| Error::config("No [target] contract in the config"))
+    }
+
+    pub fn chain_name(&self, chain_id: &U256) -> &str {
+        self.chains
+            .get(chain_id)
+            .map(|chain| chain.name.as_str())
+            .unwrap_or("UNKNOWN")
+    }
+}
+
+fn default_max_weight() -> f64 {
+    1.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_is_valid() {
+        let config = Config::from_toml(DEFAULT_CONFIG).unwrap();
+        assert_eq!(config.chains.len(), 7);
+        assert_eq!(config.sol_mints.len(), 14);
+        assert_eq!(config.token_to_oft.len(), 12);
+        assert_eq!(config.reserved.len(), 1);
+        assert_eq!(config.reserved_weight(), 1311);
+        assert_eq!(config.weight_cap(&Address::ZERO), u16::MAX);
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let contents = DEFAULT_CONFIG
+            .replace("voting_chain_id = 8453", "voting_chain_id = 999")
+            .replace(
+                "MEW1gQWJ3nEXg2qgERiKu7FAFj79PHvQVREQUzScPP5",
+                "not-a-pubkey",
+            )
+            .replace(
+                "0x1fb8432d5e243986DF8AB85aEa1DfF5030ac54b6",
+                "0x2943191025ae254A7546BdDC57e6173826e4dBc7",
+            )
+            .replace("max_weight = 1.0", "max_weight = 1.5");
+        let err = Config::from_toml(&contents).unwrap_err().to_string();
+        assert!(err.contains("voting_chain_id 999"));
+        assert!(err.contains("malformed Solana mint `not-a-pubkey`"));
+        assert!(err.contains("duplicate OFT address"));
+        assert!(err.contains("max_weight must be in [0, 1], got 1.5"));
+
+        let contents = DEFAULT_CONFIG.replace(
+            "mint = \"Dz9mQ9NzkBcCsuGPFJ3r1bS4wgqKMHBPiVuniW8Mbonk\"",
+            "mint = \"Dz9mQ9NzkBcCsuGPFJ3r1bS4wgqKMHBPiVuniW8Mbonk\"\n\
+             placeholder = \"0xa697e272a73744b343528c3bc4702f2565b2f422\"",
+        );
+        let err = Config::from_toml(&contents).unwrap_err().to_string();
+        assert!(err.contains("duplicate Solana placeholder"));
+
+        let v1 = DEFAULT_CONFIG
+            .replace("version = 2", "version = 1\njooce_int_weight = 1311")
+            .replace("[[reserved]]", "[[unused]]");
+        let err = Config::from_toml(&v1).unwrap_err().to_string();
+        assert!(err.contains("unsupported config version 1, expected 2"));
+    }
+
+    #[test]
+    fn rejects_overflowing_reserved_slots() {
+        let contents = format!(
+            "{}\n[[reserved]]\nsymbol = \"FOO\"\ntoken = \"{}\"\nchain_id = 8453\nshare = 0.99\n",
+            DEFAULT_CONFIG,
+            Address::ZERO
+        );
+        let err = Config::from_toml(&contents).unwrap_err().to_string();
+        assert!(err.contains("reserved slots take 66191 of 65535"));
+    }
+
+    #[test]
+    fn resolves_the_abi_next_to_the_config() {
+        let dir = std::env::temp_dir().join(format!("jmx-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        let target = format!(
+            "[target]\naddress = \"{}\"\nchain_id = 8453\nabi = \"abi/Index.json\"\n\
+             method = \"setWeights\"\n",
+            Address::ZERO
+        );
+        fs::write(&path, format!("{}\n{}", DEFAULT_CONFIG, target)).unwrap();
+
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(config.target().unwrap().abi, dir.join("abi/Index.json"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use alloy::primitives::{Address, U256};
+use std::{io, path::PathBuf};
+
+/// Any error source, kept boxed so the RPC and sink clients don't leak into the API.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Everything that can stop a run. Each variant names the chain, asset or file involved so
+/// the operator knows what to fix, and maps to its own exit code.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Environment variable {name} is not set")]
+    MissingEnv {
+        name: String,
+        #[source]
+        source: std::env::VarError,
+    },
+    /// The config doesn't parse, doesn't validate or lacks a section the command needs
+    #[error("{message}")]
+    Config {
+        message: String,
+        #[source]
+        source: Option<BoxError>,
+    },
+    #[error("Unknown chain id {chain_id}, add it to [[chains]] in the config")]
+    UnknownChain { chain_id: U256 },
+    #[error("No RPC url for {chain} ({chain_id}), set {env} or pass --rpc {chain_id}=<URL>")]
+    MissingRpcUrl {
+        chain: String,
+        chain_id: U256,
+        env: String,
+    },
+    #[error("No Solana mint for token {token}, add it to [[solana_mints]] in the config")]
+    UnmappedMint { token: Address },
+    /// A request to a chain failed or timed out
+    #[error("{call} failed on {chain}")]
+    Rpc {
+        chain: String,
+        call: String,
+        #[source]
+        source: BoxError,
+    },
+    /// A response or a file came back in an unexpected shape
+    #[error("Can't decode {what}: {reason}")]
+    Decode { what: String, reason: String },
+    /// The allocation broke one of its invariants, e.g. an asset without a weight
+    #[error("Allocation invariant broken: {0}")]
+    Allocation(String),
+    /// Writing to or reading from an output, the Google Sheet or the snapshot files
+    #[error("{action} failed on {sink}")]
+    Sink {
+        sink: String,
+        action: String,
+        #[source]
+        source: BoxError,
+    },
+    #[error("Can't access {}", path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub fn config(message: impl Into<String>) -> Error {
+        Error::Config {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn rpc(
+        chain: impl Into<String>,
+        call: impl Into<String>,
+        source: impl Into<BoxError>,
+    ) -> Error {
+        Error::Rpc {
+            chain: chain.into(),
+            call: call.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn decode(what: impl Into<String>, reason: impl ToString) -> Error {
+        Error::Decode {
+            what: what.into(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn sink(
+        sink: impl Into<String>,
+        action: impl Into<String>,
+        source: impl Into<BoxError>,
+    ) -> Error {
+        Error::Sink {
+            sink: sink.into(),
+            action: action.into(),
+            source: source.into(),
+        }
+    }
+
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Process exit code, following the BSD `sysexits.h` values.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::MissingEnv { .. }
+            | Error::Config { .. }
+            | Error::UnknownChain { .. }
+            | Error::MissingRpcUrl { .. }
+            | Error::UnmappedMint { .. } => 78,
+            Error::Rpc { .. } => 69,
+            Error::Decode { .. } => 65,
+            Error::Allocation(_) => 70,
+            Error::Sink { .. } | Error::Io { .. } => 74,
+        }
+    }
+}
+
+/// Reads a required environment variable.
+pub fn env_var(name: &str) -> Result<String, Error> {
+    std::env::var(name).map_err(|source| Error::MissingEnv {
+        name: name.to_owned(),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_what_failed() {
+        let err = Error::rpc(
+            "Avalanche",
+            "symbol of 3 tokens",
+            io::Error::other("connection refused"),
+        );
+        assert_eq!(err.to_string(), "symbol of 3 tokens failed on Avalanche");
+        assert_eq!(err.exit_code(), 69);
+
+        let err = Error::UnmappedMint {
+            token: Address::ZERO,
+        };
+        assert!(err.to_string().contains(&Address::ZERO.to_string()));
+        assert_eq!(err.exit_code(), 78);
+    }
+}
@@ -1,152 +1,545 @@
 use crate::{
     AssetData, IErc20,
     IJooceVoting::{self, IJooceVotingInstance},
-    variables::{ADDR_TO_SOL_MINT_ADDR, CHAIN_ID_TO_URL, SOLANA_CHAIN_ID, TOKEN_TO_OFT},
+    VotingContract,
+    config::Config,
+    error::Error,
+    symbols::{SymbolCache, SymbolSource, Ticker, fallback_ticker, print_unverified},
+    variables::{SOLANA_CHAIN_ID, WEEK},
 };
 use alloy::{
-    primitives::{Address, U256, address},
+    eips::BlockId,
+    primitives::{Address, B256, U256},
     providers::{DynProvider, Dynamic, MulticallBuilder, Provider, ProviderBuilder},
+    transports::http::reqwest::Url,
 };
+use chrono::{DateTime, NaiveDate};
+use futures::StreamExt;
 use op_alloy_network::{Ethereum, Optimism};
+use serde::{Deserialize, Serialize};
 use solana_client::rpc_client;
 use solana_sdk::pubkey::Pubkey;
-use std::collections::HashMap;
+use std::{collections::BTreeMap, str::FromStr, time::Duration};
 
+/// Calls per multicall when batching large sets, keeps each `aggregate` well under
+/// provider gas and size limits.
+pub const MULTICALL_CHUNK: usize = 400;
+/// Chain the voting contract reads are reported on.
+pub const VOTING_CHAIN: &str = "Base";
+/// Attempts per RPC url before the symbol lookup moves on to the chain's next one.
+const SYMBOL_ATTEMPTS: u32 = 3;
+/// Wait before the second attempt on an RPC url, doubled for every further one.
+const SYMBOL_BACKOFF: Duration = Duration::from_millis(250);
+
+/// A point in the past to rebuild the weights at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum At {
+    /// Unix timestamp, the assets are the ones listed at the last block before it and the
+    /// weights are read from the weekly points with `pointsOfAt`/`pointsSum`
+    Timestamp(u64),
+    /// Base block number every voting contract read is pinned to
+    Block(u64),
+}
+
+impl FromStr for At {
+    type Err = String;
+
+    /// Accepts `block:<n>` or `#<n>` for a block, and a unix timestamp, `YYYY-MM-DD`
+    /// (midnight UTC) or an RFC 3339 date for a timestamp.
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(block) = value
+            .strip_prefix("block:")
+            .or_else(|| value.strip_prefix('#'))
+        {
+            return block
+                .parse()
+                .map(At::Block)
+                .map_err(|e| format!("invalid block number `{block}`: {e}"));
+        }
+        if let Ok(timestamp) = value.parse() {
+            return Ok(At::Timestamp(timestamp));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return Ok(At::Timestamp(
+                date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64,
+            ));
+        }
+        DateTime::parse_from_rfc3339(value)
+            .map(|x| At::Timestamp(x.timestamp() as u64))
+            .map_err(|_| {
+                format!("expected a unix timestamp, YYYY-MM-DD, RFC 3339 date or block:<n>, got `{value}`")
+            })
+    }
+}
+
+/// The Base block a run pins every voting contract read to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinnedBlock {
+    pub number: u64,
+    pub hash: B256,
+    pub timestamp: u64,
+}
+
+impl PinnedBlock {
+    pub fn block_id(&self) -> BlockId {
+        BlockId::number(self.number)
+    }
+}
+
+/// Resolves the block of a run: the one given with `block:<n>`, the last one at or before
+/// the timestamp, or the latest one for live runs.
+pub async fn pin_block(
+    provider: &DynProvider<Optimism>,
+    at: Option<At>,
+) -> Result<PinnedBlock, Error> {
+    match at {
+        Some(At::Block(number)) => get_block(provider, BlockId::number(number)).await,
+        Some(At::Timestamp(time)) => {
+            let latest = get_block(provider, BlockId::latest()).await?;
+            block_at(provider, latest, time).await
+        }
+        None => get_block(provider, BlockId::latest()).await,
+    }
+}
+
+/// Last block at or before `time`, searching back from `latest`.
+pub(crate) async fn block_at(
+    provider: &DynProvider<Optimism>,
+    latest: PinnedBlock,
+    time: u64,
+) -> Result<PinnedBlock, Error> {
+    find_block_at(latest, time, |number| {
+        get_block(provider, BlockId::number(number))
+    })
+    .await
+}
+
+/// Bisects the blocks up to `latest` for the last one at or before `time`, reading them
+/// with `get_block`.
+pub(crate) async fn find_block_at<F, Fut>(
+    latest: PinnedBlock,
+    time: u64,
+    get_block: F,
+) -> Result<PinnedBlock, Error>
+where
+    F: Fn(u64) -> Fut,
+    Fut: Future<Output = Result<PinnedBlock, Error>>,
+{
+    if latest.timestamp <= time {
+        return Ok(latest);
+    }
+    // Blocks in [low, high) are still candidates, `latest` is already too late
+    let (mut low, mut high) = (0, latest.number);
+    let mut found = None;
+    while low < high {
+        let block = get_block(low + (high - low) / 2).await?;
+        if block.timestamp <= time {
+            low = block.number + 1;
+            found = Some(block);
+        } else {
+            high = block.number;
+        }
+    }
+    found.ok_or_else(|| Error::decode(format!("timestamp {}", time), "before the first block"))
+}
+
+async fn get_block(provider: &DynProvider<Optimism>, block: BlockId) -> Result<PinnedBlock, Error> {
+    let header = provider
+        .get_block(block)
+        .await
+        .map_err(|e| Error::rpc(VOTING_CHAIN, format!("eth_getBlockByNumber {}", block), e))?
+        .ok_or_else(|| Error::decode(format!("block {}", block), "not found"))?
+        .header;
+    Ok(PinnedBlock {
+        number: header.number,
+        hash: header.hash,
+        timestamp: header.timestamp,
+    })
+}
+
+/// Reads the ids of every asset listed in the voting contract at `block`.
+pub async fn get_asset_ids(contract: &VotingContract, block: BlockId) -> Result<Vec<U256>, Error> {
+    contract
+        .assets()
+        .block(block)
+        .call()
+        .await
+        .map_err(|e| Error::rpc(VOTING_CHAIN, "assets", e))
+}
+
+/// Reads `weight` of every asset at `block` in one multicall, in input order.
 pub async fn get_weight(
     provider: DynProvider<Optimism>,
     contract: &IJooceVotingInstance<DynProvider<Optimism>, Optimism>,
     asset_data: &[AssetData],
-) -> Vec<U256> {
+    block: BlockId,
+) -> Result<Vec<U256>, Error> {
     let mut multicall: MulticallBuilder<
         Dynamic<IJooceVoting::weightCall>,
         &DynProvider<Optimism>,
         Optimism,
-    > = provider.multicall().dynamic();
+    > = provider.multicall().dynamic().block(block);
 
     for asset in asset_data.iter() {
         multicall = multicall.add_dynamic(contract.weight(asset.id));
     }
-    let result = multicall.aggregate().await.unwrap();
+    let call = format!("weight of {} assets", asset_data.len());
+    let result = multicall
+        .aggregate()
+        .await
+        .map_err(|e| Error::rpc(VOTING_CHAIN, &call, e))?;
+    check_length(&call, result.len(), asset_data.len())?;
 
-    assert_eq!(&result.len(), &asset_data.len(), "Unequal arrays");
+    Ok(result)
+}
 
-    result
+/// Reads the vote points (bias) of every asset and of the sum for the week containing `time`.
+pub async fn get_points_at(
+    provider: DynProvider<Optimism>,
+    contract: &IJooceVotingInstance<DynProvider<Optimism>, Optimism>,
+    asset_data: &[AssetData],
+    time: u64,
+    block: BlockId,
+) -> Result<(Vec<U256>, U256), Error> {
+    // Points are only stored for week boundaries
+    let time = U256::from(time / WEEK * WEEK);
+    let mut multicall: MulticallBuilder<
+        Dynamic<IJooceVoting::pointsOfAtCall>,
+        &DynProvider<Optimism>,
+        Optimism,
+    > = provider.multicall().dynamic().block(block);
+
+    for asset in asset_data.iter() {
+        multicall = multicall.add_dynamic(contract.pointsOfAt(asset.id, time));
+    }
+    let points_sum = contract.pointsSum(time).block(block);
+    let (result, points_sum) = tokio::join!(multicall.aggregate(), points_sum.call());
+    let call = format!("pointsOfAt of {} assets", asset_data.len());
+    let result: Vec<U256> = result
+        .map_err(|e| Error::rpc(VOTING_CHAIN, &call, e))?
+        .into_iter()
+        .map(|x| x.bias)
+        .collect();
+    check_length(&call, result.len(), asset_data.len())?;
+    let points_sum = points_sum.map_err(|e| Error::rpc(VOTING_CHAIN, "pointsSum", e))?;
+
+    Ok((result, points_sum.bias))
 }
 
-pub type ProviderMap = HashMap<
-    U256,
-    Option<(
-        Vec<usize>,
-        DynProvider<Ethereum>,
-        MulticallBuilder<Dynamic<IErc20::symbolCall>, DynProvider, Ethereum>,
-    )>,
->;
+/// Reads `assetUpdatedAt` for every id together with the contract wide `lastTime` and `timeSum`.
+pub async fn get_asset_updated_at(
+    provider: DynProvider<Optimism>,
+    contract: &IJooceVotingInstance<DynProvider<Optimism>, Optimism>,
+    asset_ids: &[U256],
+) -> Result<(Vec<U256>, U256, U256), Error> {
+    let mut multicall: MulticallBuilder<
+        Dynamic<IJooceVoting::assetUpdatedAtCall>,
+        &DynProvider<Optimism>,
+        Optimism,
+    > = provider.multicall().dynamic();
 
-pub async fn get_ticker(asset_data: &[AssetData]) -> Vec<String> {
-    let mut provider_map: ProviderMap = HashMap::new();
+    for id in asset_ids.iter() {
+        multicall = multicall.add_dynamic(contract.assetUpdatedAt(*id));
+    }
+    let times = provider
+        .multicall()
+        .add(contract.lastTime())
+        .add(contract.timeSum());
+    let (result, times) = tokio::join!(multicall.aggregate(), times.aggregate());
+    let call = format!("assetUpdatedAt of {} assets", asset_ids.len());
+    let result = result.map_err(|e| Error::rpc(VOTING_CHAIN, &call, e))?;
+    let (last_time, time_sum) =
+        times.map_err(|e| Error::rpc(VOTING_CHAIN, "lastTime and timeSum", e))?;
+    check_length(&call, result.len(), asset_ids.len())?;
+
+    Ok((result, last_time, time_sum))
+}
+
+/// A multicall must return one result per call, anything else would shift the weights onto
+/// the wrong assets.
+fn check_length(call: &str, returned: usize, expected: usize) -> Result<(), Error> {
+    if returned != expected {
+        return Err(Error::decode(
+            call,
+            format!("{} results for {} calls", returned, expected),
+        ));
+    }
+    Ok(())
+}
+
+/// Awaits the multicall chunks a few at a time and concatenates the results in order.
+pub async fn collect_chunks<T, E, F>(chunks: impl Iterator<Item = F>) -> Result<Vec<T>, E>
+where
+    F: Future<Output = Result<Vec<T>, E>>,
+{
+    let results: Vec<Result<Vec<T>, _>> = futures::stream::iter(chunks).buffered(4).collect().await;
+    let mut out = Vec::new();
+    for result in results {
+        out.extend(result?);
+    }
+    Ok(out)
+}
+
+/// Looks up the upper-cased symbol of every asset, ERC-20 `symbol` through one multicall per
+/// chain and the token metadata accounts for Solana, in input order. Symbols are cosmetic,
+/// so a chain whose RPC urls all fail, or that has no usable url, mint mapping or config
+/// entry at all, only costs its assets their verified symbol: they fall back to the
+/// configured override or the cached one and are listed as unverified.
+pub async fn get_ticker(asset_data: &[AssetData], config: &Config) -> Vec<Ticker> {
+    let mut chain_indices: BTreeMap<U256, Vec<usize>> = BTreeMap::new();
     let mut solana_tokens: Vec<Pubkey> = Vec::new();
     let mut solana_indices: Vec<usize> = Vec::new();
-    let mut symbols: Vec<String> = vec![String::new(); asset_data.len()];
+    let mut tickers: Vec<Option<Ticker>> = vec![None; asset_data.len()];
 
     for (i, asset) in asset_data.iter().enumerate() {
         if asset.chain_id != SOLANA_CHAIN_ID {
-            provider_map
-                .entry(asset.chain_id)
-                .and_modify(|e| {
-                    let (mut indices, provider, multicall) = e.take().unwrap();
-                    indices.push(i);
-                    let contract = IErc20::new(asset.token_addr, provider.clone());
-                    let multicall = multicall.add_dynamic(contract.symbol());
-                    *e = Some((indices, provider, multicall));
-                })
-                .or_insert({
-                    let provider = ProviderBuilder::new()
-                        .connect_http(
-                            CHAIN_ID_TO_URL
-                                .get(&asset.chain_id)
-                                .unwrap()
-                                .parse()
-                                .unwrap(),
-                        )
-                        .erased();
-                    let multicall_provider = provider.multicall().with_cloned_provider().dynamic();
-                    let contract = IErc20::new(asset.token_addr, provider.clone());
-                    let mut index_vec = Vec::with_capacity(asset_data.len());
-                    index_vec.push(i);
-                    Some((
-                        index_vec,
-                        provider,
-                        multicall_provider.add_dynamic(contract.symbol()),
-                    ))
-                });
+            chain_indices.entry(asset.chain_id).or_default().push(i);
         } else {
-            let mint_pubkey = ADDR_TO_SOL_MINT_ADDR.get(&asset.token_addr).unwrap();
-            let pda = mpl_token_metadata::accounts::Metadata::find_pda(mint_pubkey).0;
-            solana_tokens.push(pda);
-            solana_indices.push(i);
+            match config.sol_mint(&asset.token_addr) {
+                Ok(mint_pubkey) => {
+                    let pda = mpl_token_metadata::accounts::Metadata::find_pda(mint_pubkey).0;
+                    solana_tokens.push(pda);
+                    solana_indices.push(i);
+                }
+                Err(e) => eprintln!("{}, using a fallback symbol", e),
+            }
         }
     }
 
-    let mut futures_vec = Vec::new();
-    let mut index_vec = Vec::new();
-    for (_k, v) in provider_map.into_iter() {
-        let (indices, _provider, multicall) = v.unwrap();
-        let fut = async move { multicall.aggregate().await };
-        futures_vec.push(fut);
-        index_vec.push(indices);
-    }
-    let results = futures::future::try_join_all(futures_vec).await.unwrap();
-    results
+    let lookups = chain_indices
         .into_iter()
-        .flatten()
-        .zip(index_vec.into_iter().flatten())
-        .for_each(|(symbol, index)| {
-            symbols[index] = symbol.to_uppercase();
+        .map(|(chain_id, indices)| async move {
+            let urls = match config.rpc_endpoints(&chain_id) {
+                Ok(val) => val,
+                Err(e) => return (indices, Err(e)),
+            };
+            let call = format!("symbol of {} tokens", indices.len());
+            let (indices_ref, call_ref) = (&indices, &call);
+            let result = with_fallbacks(&urls, move |url| async move {
+                let provider = ProviderBuilder::new().connect_http(url).erased();
+                let mut multicall: MulticallBuilder<
+                    Dynamic<IErc20::symbolCall>,
+                    &DynProvider,
+                    Ethereum,
+                > = provider.multicall().dynamic();
+                for &i in indices_ref.iter() {
+                    let contract = IErc20::new(asset_data[i].token_addr, provider.clone());
+                    multicall = multicall.add_dynamic(contract.symbol());
+                }
+                let result = multicall
+                    .aggregate()
+                    .await
+                    .map_err(|e| Error::rpc(config.chain_name(&chain_id), call_ref, e))?;
+                check_length(call_ref, result.len(), indices_ref.len())?;
+                Ok(result)
+            })
+            .await;
+            (indices, result)
         });
+    for (indices, result) in futures::future::join_all(lookups).await {
+        match result {
+            Ok(symbols) => {
+                for (index, symbol) in indices.into_iter().zip(symbols) {
+                    tickers[index] = Some(Ticker::verified(symbol.to_uppercase()));
+                }
+            }
+            Err(e) => eprintln!("{}, using fallback symbols", describe(&e)),
+        }
+    }
 
-    let sol_provider = rpc_client::RpcClient::new(CHAIN_ID_TO_URL.get(&SOLANA_CHAIN_ID).unwrap());
-    let sol_metadata_account = sol_provider.get_multiple_accounts(&solana_tokens).unwrap();
-    for (account, idx) in sol_metadata_account.iter().zip(solana_indices) {
-        if let Some(val) = account {
-            let metadata = mpl_token_metadata::accounts::Metadata::safe_deserialize(&val.data);
-            match metadata {
-                Ok(val) => {
-                    symbols[idx] = val.symbol.trim_end_matches('\0').to_uppercase();
+    if !solana_tokens.is_empty() {
+        let call = format!(
+            "getMultipleAccounts of {} metadata accounts",
+            solana_tokens.len()
+        );
+        let (solana_tokens, call) = (&solana_tokens, &call);
+        let accounts = match config.rpc_endpoints(&SOLANA_CHAIN_ID) {
+            Ok(urls) => {
+                with_fallbacks(&urls, move |url| async move {
+                    rpc_client::RpcClient::new(url.to_string())
+                        .get_multiple_accounts(solana_tokens)
+                        .map_err(|e| Error::rpc(config.chain_name(&SOLANA_CHAIN_ID), call, e))
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match accounts {
+            Ok(accounts) => {
+                for (account, idx) in accounts.iter().zip(solana_indices) {
+                    let Some(val) = account else {
+                        eprintln!("No metadata account for {:?}", asset_data[idx].token_addr);
+                        continue;
+                    };
+                    match mpl_token_metadata::accounts::Metadata::safe_deserialize(&val.data) {
+                        Ok(val) => {
+                            tickers[idx] = Some(Ticker::verified(
+                                val.symbol.trim_end_matches('\0').to_uppercase(),
+                            ));
+                        }
+                        Err(_) => {
+                            eprintln!(
+                                "Metadata decoding failed for {:?}",
+                                asset_data[idx].token_addr
+                            )
+                        }
+                    }
                 }
-                Err(_) => {
-                    println!(
-                        "Metadata decoding failed for {:?}",
-                        asset_data[idx].token_addr
-                    )
+            }
+            Err(e) => eprintln!("{}, using fallback symbols", describe(&e)),
+        }
+    }
+
+    let mut cache = match config.symbol_cache.as_deref() {
+        Some(path) => SymbolCache::load(path),
+        None => SymbolCache::default(),
+    };
+    let tickers: Vec<Ticker> = tickers
+        .into_iter()
+        .zip(asset_data)
+        .map(|(ticker, asset)| ticker.unwrap_or_else(|| fallback_ticker(asset, config, &cache)))
+        .collect();
+    if let Some(path) = config.symbol_cache.as_deref()
+        && cache.update(asset_data, &tickers)
+    {
+        cache.save(path);
+    }
+    print_unverified(asset_data, &tickers, config);
+    tickers
+}
+
+/// Runs `lookup` against each RPC url in turn, `SYMBOL_ATTEMPTS` times per url with a
+/// doubling backoff, and returns the first success or the last error.
+async fn with_fallbacks<T, F, Fut>(urls: &[Url], lookup: F) -> Result<T, Error>
+where
+    F: Fn(Url) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let mut last_error = None;
+    for url in urls {
+        let mut backoff = SYMBOL_BACKOFF;
+        for attempt in 1..=SYMBOL_ATTEMPTS {
+            match lookup(url.clone()).await {
+                Ok(val) => return Ok(val),
+                Err(e) => {
+                    // Only the host, the rest of an RPC url often holds an API key
+                    eprintln!(
+                        "{} (attempt {} of {} on {})",
+                        describe(&e),
+                        attempt,
+                        SYMBOL_ATTEMPTS,
+                        url.host_str().unwrap_or("?")
+                    );
+                    last_error = Some(e);
                 }
             }
+            if attempt < SYMBOL_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
         }
     }
-    symbols
+    Err(last_error.unwrap_or_else(|| Error::config("No RPC url to look the symbols up on")))
 }
 
-pub fn decode_asset_ids(asset_ids: &[U256]) -> Vec<AssetData> {
+/// An error with its immediate cause, for the warnings of a lookup that is recovered from.
+fn describe(e: &Error) -> String {
+    match std::error::Error::source(e) {
+        Some(source) => format!("{}: {}", e, source),
+        None => e.to_string(),
+    }
+}
+
+/// Packs a token and its chain id the way the voting contract does, chain id in the top 12
+/// bytes and the address in the low 20.
+pub fn encode_asset_id(token_addr: &Address, chain_id: &U256) -> U256 {
+    (*chain_id << 160) | U256::from_be_slice(token_addr.as_slice())
+}
+
+/// Splits voting contract ids into token and chain id, with the OFT address from the config.
+pub fn decode_asset_ids(asset_ids: &[U256], config: &Config) -> Vec<AssetData> {
     let mut out = Vec::with_capacity(asset_ids.len());
     for x in asset_ids {
         let byte_array = x.to_be_bytes::<32>();
         let mut token_addr = Address::from_slice(&byte_array[12..]);
-        let chain_id = if token_addr == address!("0xa697e272a73744b343528c3bc4702f2565b2f422") {
-            token_addr = address!("0x9BcbE99c5de789156Aa30eE47C0447BEac2a3B4c");
-            SOLANA_CHAIN_ID
-        } else {
-            U256::from_be_slice(&byte_array[0..12])
+        let chain_id = match config.sol_placeholders.get(&token_addr) {
+            Some(token) => {
+                token_addr = *token;
+                SOLANA_CHAIN_ID
+            }
+            None => U256::from_be_slice(&byte_array[0..12]),
         };
 
         out.push(AssetData {
             id: *x,
             token_addr,
-            oft_address: *TOKEN_TO_OFT.get(&token_addr).unwrap_or(&token_addr),
+            oft_address: *config.token_to_oft.get(&token_addr).unwrap_or(&token_addr),
             chain_id,
+            weight: None,
             relative_weight: None,
             actual_weight: None,
             converted_weight: None,
             symbol: None,
+            symbol_source: SymbolSource::Chain,
         });
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    #[test]
+    fn parses_historical_points() {
+        assert_eq!("block:123".parse(), Ok(At::Block(123)));
+        assert_eq!("#123".parse(), Ok(At::Block(123)));
+        assert_eq!("1717200000".parse(), Ok(At::Timestamp(1717200000)));
+        assert_eq!("2024-06-01".parse(), Ok(At::Timestamp(1717200000)));
+        assert_eq!(
+            "2024-06-01T00:00:00Z".parse(),
+            Ok(At::Timestamp(1717200000))
+        );
+        assert!("yesterday".parse::<At>().is_err());
+    }
+
+    #[tokio::test]
+    async fn finds_the_block_at_a_timestamp() {
+        // Two second blocks from t = 1000, with a gap after block 50
+        let block = |number: u64| PinnedBlock {
+            number,
+            hash: B256::ZERO,
+            timestamp: 1000 + 2 * number + if number > 50 { 600 } else { 0 },
+        };
+        let find = |time| find_block_at(block(100), time, move |n| async move { Ok(block(n)) });
+
+        assert_eq!(find(1000).await.unwrap().number, 0);
+        assert_eq!(find(1011).await.unwrap().number, 5);
+        assert_eq!(find(1500).await.unwrap().number, 50);
+        assert_eq!(find(1702).await.unwrap().number, 51);
+        assert_eq!(find(5000).await.unwrap().number, 100);
+        assert!(find(999).await.is_err());
+    }
+
+    #[test]
+    fn encodes_asset_ids() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let token = address!("0x100CE3E3391C00B6A52911313A4Ea8D23c8a38D8");
+        let id = encode_asset_id(&token, &U256::from(8453));
+        let decoded = &decode_asset_ids(&[id], &config)[0];
+        assert_eq!(decoded.token_addr, token);
+        assert_eq!(decoded.chain_id, U256::from(8453));
+
+        // Listed under a Base placeholder, decoded to the Solana token
+        let placeholder = address!("0xa697e272a73744b343528c3bc4702f2565b2f422");
+        let id = encode_asset_id(&placeholder, &U256::from(8453));
+        let decoded = &decode_asset_ids(&[id], &config)[0];
+        assert_eq!(
+            decoded.token_addr,
+            address!("0x9BcbE99c5de789156Aa30eE47C0447BEac2a3B4c")
+        );
+        assert_eq!(decoded.chain_id, SOLANA_CHAIN_ID);
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{
+    AssetData, IJooceVoting, VotingContract,
+    allocation::AllocationStrategy,
+    config::Config,
+    error::Error,
+    fetch_data::{
+        MULTICALL_CHUNK, VOTING_CHAIN, collect_chunks, decode_asset_ids, get_asset_ids,
+        get_asset_updated_at, get_ticker,
+    },
+    finalize_allocation, u256_division,
+    variables::WEEK,
+};
+use alloy::{
+    eips::BlockId,
+    primitives::U256,
+    providers::{DynProvider, Dynamic, MulticallBuilder, Provider},
+};
+use chrono::{DateTime, Utc};
+use op_alloy_network::Optimism;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct ForecastWeek {
+    pub timestamp: u64,
+    /// Final allocation for the week, same order and content as a live run
+    pub allocation: Vec<AssetData>,
+}
+
+/// Decays a `(slope, bias)` point week by week, assuming no new votes: the bias drops by
+/// `slope * WEEK` and the slope by the scheduled change as locks expire. `changes[k]` is the
+/// slope change at the start of week `k + 1`.
+pub fn project_bias(mut slope: U256, mut bias: U256, changes: &[U256]) -> Vec<U256> {
+    changes
+        .iter()
+        .map(|change| {
+            bias = bias.saturating_sub(slope * U256::from(WEEK));
+            slope = slope.saturating_sub(*change);
+            bias
+        })
+        .collect()
+}
+
+/// Week of the last checkpoint at `updated_at`, the points are final from there on. Never
+/// checkpointed means no votes, read at `start` like a current one.
+fn checkpointed_week(updated_at: U256, start: u64) -> u64 {
+    match updated_at.is_zero() {
+        true => start,
+        false => (updated_at.saturating_to::<u64>() / WEEK * WEEK).min(start),
+    }
+}
+
+/// Projects the allocation `weeks` weeks ahead from `pointsOfAt`/`slopeChangesOfAt` of every
+/// asset and `pointsSum`/`changesSum` of the total. The points of this week only exist once
+/// it was checkpointed, so whatever wasn't yet is projected from its last checkpoint.
+pub async fn forecast_weights(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    config: &Config,
+    strategy: &dyn AllocationStrategy,
+    weeks: u64,
+) -> Result<Vec<ForecastWeek>, Error> {
+    let asset_ids = get_asset_ids(contract, BlockId::latest()).await?;
+    let mut asset_data = decode_asset_ids(&asset_ids, config);
+    let tickers = get_ticker(&asset_data, config).await;
+    for (asset, ticker) in asset_data.iter_mut().zip(tickers) {
+        asset.set_ticker(ticker);
+    }
+
+    let start = Utc::now().timestamp() as u64 / WEEK * WEEK;
+    let end = start + weeks * WEEK;
+    let times: Vec<U256> = (1..=weeks).map(|k| U256::from(start + k * WEEK)).collect();
+    let (updated_at, _, time_sum) =
+        get_asset_updated_at(provider.clone(), contract, &asset_ids).await?;
+    let from: Vec<u64> = updated_at
+        .iter()
+        .map(|x| checkpointed_week(*x, start))
+        .collect();
+    let sum_from = checkpointed_week(time_sum, start);
+    let behind = from.iter().filter(|x| **x < start).count();
+    if behind > 0 || sum_from < start {
+        eprintln!(
+            "{} assets{} not checkpointed this week, projecting them from their last checkpoint",
+            behind,
+            if sum_from < start { " and the sum" } else { "" }
+        );
+    }
+
+    // Every asset's slope changes from its checkpoint to the end, asset major
+    let week_range = |from: u64| (from + WEEK..=end).step_by(WEEK as usize).map(U256::from);
+    let point_pairs: Vec<(U256, U256)> = asset_ids
+        .iter()
+        .zip(from.iter())
+        .map(|(id, from)| (*id, U256::from(*from)))
+        .collect();
+    let change_pairs: Vec<(U256, U256)> = asset_ids
+        .iter()
+        .zip(from.iter())
+        .flat_map(|(id, from)| week_range(*from).map(move |time| (*id, time)))
+        .collect();
+    let sum_times: Vec<U256> = week_range(sum_from).collect();
+
+    let points_sum = contract.pointsSum(U256::from(sum_from));
+    let (points, changes, points_sum, changes_sum) = tokio::join!(
+        get_points(provider, contract, &point_pairs),
+        get_slope_changes(provider, contract, &change_pairs),
+        points_sum.call(),
+        get_changes_sum(provider, contract, &sum_times)
+    );
+    let points_sum = points_sum.map_err(|e| Error::rpc(VOTING_CHAIN, "pointsSum", e))?;
+    let (points, changes, changes_sum) = (points?, changes?, changes_sum?);
+
+    // Projecting from an older checkpoint decays through the weeks up to `start` first, only
+    // the last `weeks` values are kept
+    let mut changes = changes.into_iter();
+    let asset_bias: Vec<Vec<U256>> = points
+        .iter()
+        .zip(from.iter())
+        .map(|(point, from)| {
+            let changes: Vec<U256> = changes.by_ref().take(week_range(*from).count()).collect();
+            let bias = project_bias(point.slope, point.bias, &changes);
+            bias[bias.len() - times.len()..].to_vec()
+        })
+        .collect();
+    let sum_bias = project_bias(points_sum.slope, points_sum.bias, &changes_sum);
+    let sum_bias = &sum_bias[sum_bias.len() - times.len()..];
+
+    let mut out = Vec::with_capacity(times.len());
+    for (k, time) in times.iter().enumerate() {
+        if sum_bias[k].is_zero() {
+            break;
+        }
+        let mut weighted = asset_data.clone();
+        for (asset, bias) in weighted.iter_mut().zip(asset_bias.iter()) {
+            asset.weight = Some(bias[k]);
+            asset.relative_weight = u256_division(&bias[k], &sum_bias[k]);
+        }
+        out.push(ForecastWeek {
+            timestamp: time.to(),
+            allocation: finalize_allocation(weighted, config, strategy)?,
+        });
+    }
+    Ok(out)
+}
+
+/// Points of every `(asset id, time)` pair.
+async fn get_points(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    pairs: &[(U256, U256)],
+) -> Result<Vec<IJooceVoting::pointsOfAtReturn>, Error> {
+    let chunks = pairs.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::pointsOfAtCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic();
+        for (id, time) in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.pointsOfAt(*id, *time));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "pointsOfAt", e))
+    });
+    collect_chunks(chunks).await
+}
+
+/// Slope changes of every `(asset id, time)` pair.
+async fn get_slope_changes(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    pairs: &[(U256, U256)],
+) -> Result<Vec<U256>, Error> {
+    let chunks = pairs.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::slopeChangesOfAtCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic();
+        for (id, time) in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.slopeChangesOfAt(*id, *time));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "slopeChangesOfAt", e))
+    });
+    collect_chunks(chunks).await
+}
+
+async fn get_changes_sum(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    times: &[U256],
+) -> Result<Vec<U256>, Error> {
+    let chunks = times.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::changesSumCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic();
+        for time in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.changesSum(*time));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "changesSum", e))
+    });
+    collect_chunks(chunks).await
+}
+
+/// Prints the u16 allocation of every week next to the current one and flags the first week
+/// any asset drifts by more than `threshold` of the index.
+pub fn print_forecast(current: &[AssetData], forecast: &[ForecastWeek], threshold: f64) {
+    let mut header = format!("{:<12} {:>8}", "Asset", "now");
+    for week in forecast.iter() {
+        let date = DateTime::from_timestamp(week.timestamp as i64, 0)
+            .map(|x| x.format("%m-%d").to_string())
+            .unwrap_or_default();
+        header.push_str(&format!(" {:>8}", date));
+    }
+    println!("{}", header);
+
+    let weeks: Vec<HashMap<U256, &AssetData>> = forecast
+        .iter()
+        .map(|week| week.allocation.iter().map(|x| (x.id, x)).collect())
+        .collect();
+    for asset in current.iter() {
+        let mut line = format!(
+            "{:<12} {:>8}",
+            asset.report_symbol(),
+            asset.converted_weight.unwrap_or_default()
+        );
+        for week in weeks.iter() {
+            let weight = week.get(&asset.id).and_then(|x| x.converted_weight);
+            line.push_str(&format!(
+                " {:>8}",
+                weight.map(|x| x.to_string()).unwrap_or("-".to_owned())
+            ));
+        }
+        println!("{}", line);
+    }
+
+    let breach = forecast
+        .iter()
+        .zip(weeks.iter())
+        .find_map(|(week, weights)| {
+            current
+                .iter()
+                .map(|asset| {
+                    let before = asset.actual_weight.unwrap_or_default();
+                    let after = weights
+                        .get(&asset.id)
+                        .and_then(|x| x.actual_weight)
+                        .unwrap_or_default();
+                    (asset, (after - before).abs())
+                })
+                .find(|(_, drift)| *drift > threshold)
+                .map(|(asset, drift)| (week.timestamp, asset, drift))
+        });
+    match breach {
+        Some((timestamp, asset, drift)) => println!(
+            "{} drifts by {:.2}% in the week of {}, rebalance by then",
+            asset.report_symbol(),
+            drift * 100.,
+            DateTime::from_timestamp(timestamp as i64, 0)
+                .map(|x| x.format("%Y-%m-%d").to_string())
+                .unwrap_or_default()
+        ),
+        None => println!(
+            "No asset drifts by more than {:.2}% within {} weeks",
+            threshold * 100.,
+            forecast.len()
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projects_from_the_last_checkpoint() {
+        let start = 100 * WEEK;
+        assert_eq!(checkpointed_week(U256::ZERO, start), start);
+        assert_eq!(checkpointed_week(U256::from(start + 5), start), start);
+        assert_eq!(
+            checkpointed_week(U256::from(start - 5), start),
+            start - WEEK
+        );
+
+        // A point two weeks old decays to the same values as this week's one would
+        let slope = U256::from(10);
+        let old_bias = slope * U256::from(6 * WEEK);
+        let from_old = project_bias(slope, old_bias, &[U256::ZERO; 5]);
+        let from_current = project_bias(slope, from_old[1], &[U256::ZERO; 3]);
+        assert_eq!(from_old[2..], from_current[..]);
+    }
+
+    #[test]
+    fn bias_decays_until_locks_expire() {
+        let slope = U256::from(10);
+        let bias = slope * U256::from(3 * WEEK);
+        // The whole slope expires at the start of week three
+        let changes = [U256::ZERO, U256::ZERO, slope, U256::ZERO];
+        let projected = project_bias(slope, bias, &changes);
+        assert_eq!(
+            projected,
+            vec![
+                slope * U256::from(2 * WEEK),
+                slope * U256::from(WEEK),
+                U256::ZERO,
+                U256::ZERO
+            ]
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use crate::{
+    IJooceVoting::{AssetAdded, Vote},
+    VotingContract,
+    error::Error,
+    fetch_data::VOTING_CHAIN,
+};
+use alloy::{
+    primitives::{Address, TxHash, U256},
+    providers::Provider,
+    rpc::types::{Filter, Log},
+    sol_types::SolEvent,
+};
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+
+/// Smallest block range the indexer falls back to when the RPC rejects a query.
+const MIN_CHUNK_SIZE: u64 = 10;
+/// How database errors name the vote index.
+const VOTE_INDEX: &str = "the vote index";
+
+#[derive(Debug, Clone)]
+pub struct VoteRecord {
+    pub block_number: u64,
+    pub tx_hash: TxHash,
+    pub user: Address,
+    pub asset_id: U256,
+    pub weight: U256,
+}
+
+/// Opens the vote index, creating the tables if needed.
+pub fn open_database(path: &Path) -> Result<Connection, Error> {
+    let db = Connection::open(path).map_err(|e| {
+        Error::sink(
+            format!("{} {}", VOTE_INDEX, path.display()),
+            "Opening the database",
+            e,
+        )
+    })?;
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS index_state (
+            contract TEXT PRIMARY KEY,
+            last_block INTEGER NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS votes (
+            contract TEXT NOT NULL,
+            block_number INTEGER NOT NULL,
+            tx_hash TEXT NOT NULL,
+            log_index INTEGER NOT NULL,
+            user TEXT NOT NULL,
+            asset_id TEXT NOT NULL,
+            weight TEXT NOT NULL,
+            PRIMARY KEY (tx_hash, log_index)
+        );
+        CREATE INDEX IF NOT EXISTS votes_asset ON votes (contract, asset_id, block_number);
+        CREATE INDEX IF NOT EXISTS votes_user ON votes (contract, user);
+        CREATE TABLE IF NOT EXISTS assets_added (
+            contract TEXT NOT NULL,
+            block_number INTEGER NOT NULL,
+            tx_hash TEXT NOT NULL,
+            log_index INTEGER NOT NULL,
+            asset_id TEXT NOT NULL,
+            PRIMARY KEY (tx_hash, log_index)
+        );",
+    )
+    .map_err(db_error("Creating the tables"))?;
+    Ok(db)
+}
+
+pub fn last_indexed_block(db: &Connection, contract: Address) -> Result<Option<u64>, Error> {
+    let last_block: Option<i64> = db
+        .query_row(
+            "SELECT last_block FROM index_state WHERE contract = ?1",
+            params![contract.to_string()],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(db_error("Reading the last indexed block"))?;
+    Ok(last_block.map(|x| x as u64))
+}
+
+/// Backfills `Vote` and `AssetAdded` logs of the voting contract into `db`, resuming after
+/// the last indexed block or starting at `from_block` on an empty database, which then has
+/// to be given. Blocks newer than `confirmations` behind the head are left for the next run.
+pub async fn index_events(
+    contract: &VotingContract,
+    db: &mut Connection,
+    from_block: Option<u64>,
+    chunk_size: u64,
+    confirmations: u64,
+) -> Result<(), Error> {
+    let address = *contract.address();
+    let provider = contract.provider();
+    let mut start = match last_indexed_block(db, address)? {
+        Some(val) => val + 1,
+        None => from_block.ok_or_else(|| {
+            Error::config(
+                "the database is empty, pass --from-block or set voting_deploy_block in the config",
+            )
+        })?,
+    };
+    let head = provider
+        .get_block_number()
+        .await
+        .map_err(|e| Error::rpc(VOTING_CHAIN, "eth_blockNumber", e))?
+        .saturating_sub(confirmations);
+    let mut chunk_size = chunk_size.max(MIN_CHUNK_SIZE);
+    let (mut votes, mut assets) = (0usize, 0usize);
+
+    while start <= head {
+        let end = (start + chunk_size - 1).min(head);
+        let filter = Filter::new()
+            .address(address)
+            .event_signature(vec![Vote::SIGNATURE_HASH, AssetAdded::SIGNATURE_HASH])
+            .from_block(start)
+            .to_block(end);
+        let logs = match provider.get_logs(&filter).await {
+            Ok(val) => val,
+            // Most providers cap the range or the number of results, retry with less
+            Err(e) if chunk_size > MIN_CHUNK_SIZE => {
+                chunk_size /= 2;
+                println!(
+                    "get_logs {}-{} failed ({}), chunk size now {}",
+                    start, end, e, chunk_size
+                );
+                continue;
+            }
+            Err(e) => {
+                return Err(Error::rpc(
+                    VOTING_CHAIN,
+                    format!("eth_getLogs {}-{}", start, end),
+                    e,
+                ));
+            }
+        };
+
+        let tx = db
+            .transaction()
+            .map_err(db_error("Starting a transaction"))?;
+        for log in logs.iter() {
+            match store_log(&tx, address, log)? {
+                Some(true) => votes += 1,
+                Some(false) => assets += 1,
+                None => {}
+            }
+        }
+        tx.execute(
+            "INSERT INTO index_state (contract, last_block) VALUES (?1, ?2)
+             ON CONFLICT (contract) DO UPDATE SET last_block = excluded.last_block",
+            params![address.to_string(), end as i64],
+        )
+        .map_err(db_error("Saving the last indexed block"))?;
+        tx.commit().map_err(db_error("Committing the logs"))?;
+
+        println!("Indexed blocks {}-{} ({} logs)", start, end, logs.len());
+        start = end + 1;
+    }
+    println!(
+        "Indexed {} votes and {} added assets, up to block {}",
+        votes, assets, head
+    );
+    Ok(())
+}
+
+/// Stores one log, returns whether it was a vote (`Some(true)`) or an added asset.
+fn store_log(
+    tx: &rusqlite::Transaction,
+    contract: Address,
+    log: &Log,
+) -> Result<Option<bool>, Error> {
+    let missing = |field: &str| Error::decode("a log", format!("no {}", field));
+    let block_number = log.block_number.ok_or_else(|| missing("block number"))?;
+    let tx_hash = log
+        .transaction_hash
+        .ok_or_else(|| missing("transaction hash"))?;
+    let log_index = log.log_index.ok_or_else(|| missing("log index"))?;
+    let invalid =
+        |event: &str, e| Error::decode(format!("{} log {}:{}", event, tx_hash, log_index), e);
+
+    match log.topic0() {
+        Some(&Vote::SIGNATURE_HASH) => {
+            let vote = log
+                .log_decode::<Vote>()
+                .map_err(|e| invalid("Vote", e))?
+                .inner
+                .data;
+            tx.execute(
+                "INSERT OR IGNORE INTO votes
+                 (contract, block_number, tx_hash, log_index, user, asset_id, weight)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    contract.to_string(),
+                    block_number as i64,
+                    tx_hash.to_string(),
+                    log_index as i64,
+                    vote.user.to_string(),
+                    vote.assetId.to_string(),
+                    vote.weight.to_string()
+                ],
+            )
+            .map_err(db_error("Storing a vote"))?;
+            Ok(Some(true))
+        }
+        Some(&AssetAdded::SIGNATURE_HASH) => {
+            let added = log
+                .log_decode::<AssetAdded>()
+                .map_err(|e| invalid("AssetAdded", e))?
+                .inner
+                .data;
+            tx.execute(
+                "INSERT OR IGNORE INTO assets_added
+                 (contract, block_number, tx_hash, log_index, asset_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    contract.to_string(),
+                    block_number as i64,
+                    tx_hash.to_string(),
+                    log_index as i64,
+                    added.assetId.to_string()
+                ],
+            )
+            .map_err(db_error("Storing an added asset"))?;
+            Ok(Some(false))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Every address that has voted on the contract.
+pub fn distinct_voters(db: &Connection, contract: Address) -> Result<Vec<Address>, Error> {
+    let mut statement = db
+        .prepare("SELECT DISTINCT user FROM votes WHERE contract = ?1 ORDER BY user")
+        .map_err(db_error("Reading the voters"))?;
+    let rows = statement
+        .query_map(params![contract.to_string()], |row| row.get::<_, String>(0))
+        .map_err(db_error("Reading the voters"))?;
+    let mut out = Vec::new();
+    for row in rows {
+        let user = row.map_err(db_error("Reading the voters"))?;
+        out.push(user.parse().map_err(|e| invalid_row("user", &user, e))?);
+    }
+    Ok(out)
+}
+
+/// Most recent votes for an asset, newest first.
+pub fn votes_for_asset(
+    db: &Connection,
+    contract: Address,
+    asset_id: U256,
+    limit: usize,
+) -> Result<Vec<VoteRecord>, Error> {
+    let mut statement = db
+        .prepare(
+            "SELECT block_number, tx_hash, user, asset_id, weight FROM votes
+         WHERE contract = ?1 AND asset_id = ?2
+         ORDER BY block_number DESC, log_index DESC LIMIT ?3",
+        )
+        .map_err(db_error("Reading the votes"))?;
+    let rows = statement.query_map(
+        params![contract.to_string(), asset_id.to_string(), limit as i64],
+        |row| {
+            Ok((
+                row.get::<_, i64>(0)? as u64,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+            ))
+        },
+    );
+    let rows = rows.map_err(db_error("Reading the votes"))?;
+
+    let mut out = Vec::new();
+    for row in rows {
+        let (block_number, tx_hash, user, asset_id, weight) =
+            row.map_err(db_error("Reading the votes"))?;
+        out.push(VoteRecord {
+            block_number,
+            tx_hash: tx_hash
+                .parse()
+                .map_err(|e| invalid_row("tx_hash", &tx_hash, e))?,
+            user: user.parse().map_err(|e| invalid_row("user", &user, e))?,
+            asset_id: asset_id
+                .parse()
+                .map_err(|e| invalid_row("asset_id", &asset_id, e))?,
+            weight: weight
+                .parse()
+                .map_err(|e| invalid_row("weight", &weight, e))?,
+        });
+    }
+    Ok(out)
+}
+
+fn db_error(action: &str) -> impl FnOnce(rusqlite::Error) -> Error + '_ {
+    move |e| Error::sink(VOTE_INDEX, action, e)
+}
+
+fn invalid_row(column: &str, value: &str, reason: impl ToString) -> Error {
+    Error::decode(format!("{} `{}` in {}", column, value, VOTE_INDEX), reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    #[test]
+    fn stores_and_queries_votes() {
+        let contract = address!("0xdD5CB392A549644295862f96f25484a56FB2e6a8");
+        let user = address!("0x0000000000000000000000000000000000000001");
+        let vote = Vote {
+            user,
+            assetId: U256::from(7),
+            weight: U256::from(5000),
+        };
+        let log = Log {
+            inner: alloy::primitives::Log {
+                address: contract,
+                data: vote.encode_log_data(),
+            },
+            block_number: Some(100),
+            transaction_hash: Some(TxHash::repeat_byte(1)),
+            log_index: Some(0),
+            ..Log::default()
+        };
+
+        let mut db = open_database(Path::new(":memory:")).unwrap();
+
+        let tx = db.transaction().unwrap();
+        assert_eq!(store_log(&tx, contract, &log).unwrap(), Some(true));
+        // Re-indexing the same log is a no-op
+        store_log(&tx, contract, &log).unwrap();
+        tx.commit().unwrap();
+
+        assert_eq!(distinct_voters(&db, contract).unwrap(), vec![user]);
+        let votes = votes_for_asset(&db, contract, U256::from(7), 10).unwrap();
+        assert_eq!(votes.len(), 1);
+        assert_eq!(votes[0].user, user);
+        assert_eq!(votes[0].weight, U256::from(5000));
+    }
+}
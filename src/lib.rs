@@ -0,0 +1,537 @@
+//! Fetches the Jooce voting weights and turns them into the JMX index allocation.
+//!
+//! [`WeightClient`] reads the voting contract on Base, [`finalize_allocation`] applies the
+//! filters, reserved slots and an [`AllocationStrategy`] to produce the `u16` weights, and
+//! the output sinks live in [`write_data`] (Google Sheet), [`snapshot`] (JSON and CSV files),
+//! [`safe`] (Safe Transaction Builder batches) and [`target`] (calldata). The `jmx-weights`
+//! binary is a command line front end over this crate.
+
+pub mod allocation;
+pub mod checkpoint;
+pub mod config;
+pub mod error;
+pub mod fetch_data;
+pub mod forecast;
+pub mod indexer;
+#[cfg(test)]
+mod mock_rpc;
+pub mod read_data;
+pub mod safe;
+pub mod snapshot;
+pub mod symbols;
+pub mod target;
+pub mod timeseries;
+pub mod turnover;
+pub mod variables;
+pub mod verify;
+pub mod voters;
+pub mod write_data;
+
+pub use error::Error;
+use std::{borrow::Cow, cmp::Reverse};
+
+use crate::{
+    IJooceVoting::IJooceVotingInstance,
+    allocation::{AllocationStrategy, capped_largest_remainder},
+    config::Config,
+    fetch_data::{
+        At, PinnedBlock, VOTING_CHAIN, decode_asset_ids, encode_asset_id, get_asset_ids,
+        get_points_at, get_ticker, get_weight, pin_block,
+    },
+    symbols::{SymbolSource, Ticker},
+    variables::{SCALE, WEEK},
+};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, U256, U512},
+    providers::{DynProvider, Provider, ProviderBuilder},
+    sol,
+};
+use chrono::{DateTime, Local};
+use op_alloy_network::Optimism;
+use serde::{Deserialize, Serialize};
+
+/// One asset of the voting contract, filled in as it goes through the pipeline.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssetData {
+    /// Voting contract id, the chain id in the top 12 bytes and the token in the low 20
+    pub id: U256,
+    pub token_addr: Address,
+    /// Address the index holds the asset under, the token itself when it has no OFT
+    pub oft_address: Address,
+    pub symbol: Option<String>,
+    /// Unverified when the token's chain couldn't be read and the symbol is a fallback
+    #[serde(default)]
+    pub symbol_source: SymbolSource,
+    pub chain_id: U256,
+    /// Raw vote weight the allocation is apportioned from
+    pub weight: Option<U256>,
+    /// Share of the total vote
+    pub relative_weight: Option<f64>,
+    /// Share of the index, `converted_weight` over `u16::MAX`
+    pub actual_weight: Option<f64>,
+    /// Weight in the index, all of them sum to `u16::MAX`
+    pub converted_weight: Option<u16>,
+}
+
+impl AssetData {
+    /// Symbol and token address, how errors and reports name an asset.
+    pub fn label(&self) -> String {
+        match self.symbol.as_deref() {
+            Some(symbol) if !symbol.is_empty() => format!("{} ({})", symbol, self.token_addr),
+            _ => self.token_addr.to_string(),
+        }
+    }
+
+    /// Symbol for the printed reports, marked with `*` when it wasn't read from the chain.
+    pub fn report_symbol(&self) -> String {
+        let symbol = self.symbol.as_deref().unwrap_or("");
+        match self.symbol_source.is_verified() {
+            true => symbol.to_owned(),
+            false => format!("{}*", symbol),
+        }
+    }
+
+    /// Sets the symbol returned by `get_ticker`.
+    pub fn set_ticker(&mut self, ticker: Ticker) {
+        self.symbol = ticker.symbol;
+        self.symbol_source = ticker.source;
+    }
+
+    /// Weight in the index, an error for an asset that hasn't been through the allocation.
+    pub fn allocated_weight(&self) -> Result<u16, Error> {
+        self.converted_weight
+            .ok_or_else(|| Error::Allocation(format!("{} has no u16 weight", self.label())))
+    }
+}
+
+#[cfg(test)]
+impl AssetData {
+    /// Asset `i` of the unit tests: a Base token and OFT at `i` repeated, symbol `T<i>`.
+    pub(crate) fn test_asset(i: u8, converted_weight: Option<u16>) -> AssetData {
+        AssetData {
+            id: U256::from(i),
+            token_addr: Address::repeat_byte(i),
+            oft_address: Address::repeat_byte(i),
+            symbol: Some(format!("T{}", i)),
+            symbol_source: SymbolSource::Chain,
+            chain_id: U256::from(8453),
+            weight: None,
+            relative_weight: None,
+            actual_weight: converted_weight.map(|x| x as f64 / u16::MAX as f64),
+            converted_weight,
+        }
+    }
+}
+
+/// One fetch of the voting contract, every read pinned to `block`.
+#[derive(Debug, Clone)]
+pub struct FetchedWeights {
+    pub assets: Vec<AssetData>,
+    pub weights_sum: U256,
+    pub block: PinnedBlock,
+}
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    #[derive(Debug)]
+    IJooceVoting,
+    "abi/JooceVoting.json"
+);
+
+sol!(
+    #[allow(missing_docs)]
+    #[sol(rpc)]
+    IErc20,
+    "abi/Erc20.json"
+);
+
+/// The voting contract bound to a Base provider.
+pub type VotingContract = IJooceVotingInstance<DynProvider<Optimism>, Optimism>;
+
+/// Reads the voting contract described by a [`Config`].
+#[derive(Clone)]
+pub struct WeightClient {
+    config: Config,
+    provider: DynProvider<Optimism>,
+    contract: VotingContract,
+}
+
+impl WeightClient {
+    /// Connects to the RPC url of the config's voting chain.
+    pub fn new(config: Config) -> Result<WeightClient, Error> {
+        let url = config.rpc_endpoint(&config.voting_chain_id)?;
+        let provider = ProviderBuilder::new_with_network::<Optimism>()
+            .connect_http(url)
+            .erased();
+        Ok(WeightClient::with_provider(config, provider))
+    }
+
+    /// Uses an existing provider of the voting chain, e.g. one with a wallet.
+    pub fn with_provider(config: Config, provider: DynProvider<Optimism>) -> WeightClient {
+        let contract = IJooceVoting::new(config.voting_contract, provider.clone());
+        WeightClient {
+            config,
+            provider,
+            contract,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn provider(&self) -> &DynProvider<Optimism> {
+        &self.provider
+    }
+
+    pub fn contract(&self) -> &VotingContract {
+        &self.contract
+    }
+
+    /// Reads every asset from the voting contract along with its relative weight and
+    /// symbol, and the total weight. Every voting contract read is pinned to one block, the
+    /// one given with `at` or the latest. With a timestamp the assets are listed at the last
+    /// block before it and the weights are rebuilt from the weekly points.
+    pub async fn fetch(&self, at: Option<At>) -> Result<FetchedWeights, Error> {
+        let pinned = pin_block(&self.provider, at).await?;
+        let block = pinned.block_id();
+        let asset_ids = get_asset_ids(&self.contract, block).await?;
+        let mut decoded_data = decode_asset_ids(&asset_ids, &self.config);
+
+        let weights = async {
+            match at {
+                Some(At::Timestamp(time)) => {
+                    // The points of a week are only filled in by the first checkpoint after
+                    // it starts, the latest block has them whenever that happened
+                    get_points_at(
+                        self.provider.clone(),
+                        &self.contract,
+                        &decoded_data,
+                        time,
+                        BlockId::latest(),
+                    )
+                    .await
+                }
+                _ => {
+                    let total_weight = self.contract.weightsSum().block(block);
+                    let (weights, total_weight) = tokio::join!(
+                        get_weight(self.provider.clone(), &self.contract, &decoded_data, block),
+                        total_weight.call()
+                    );
+                    let total_weight =
+                        total_weight.map_err(|e| Error::rpc(VOTING_CHAIN, "weightsSum", e))?;
+                    Ok((weights?, total_weight))
+                }
+            }
+        };
+        let (weights, symbols) = tokio::join!(weights, get_ticker(&decoded_data, &self.config));
+        let (weights, total_weight) = weights?;
+        if total_weight.is_zero() {
+            return Err(Error::Allocation(match at {
+                Some(At::Timestamp(time)) => format!(
+                    "no vote points in the week of {}, it has no votes or was never checkpointed",
+                    time / WEEK * WEEK
+                ),
+                _ => format!("weightsSum is 0 at block {}", pinned.number),
+            }));
+        }
+        for (i, asset) in decoded_data.iter_mut().enumerate() {
+            asset.weight = Some(weights[i]);
+            asset.relative_weight = u256_division(&weights[i], &total_weight);
+            asset.set_ticker(symbols[i].clone());
+        }
+        Ok(FetchedWeights {
+            assets: decoded_data,
+            weights_sum: total_weight,
+            block: pinned,
+        })
+    }
+
+    /// Fetches the weights and turns them into the final allocation, reserved slots
+    /// included.
+    pub async fn allocate(
+        &self,
+        strategy: &dyn AllocationStrategy,
+        at: Option<At>,
+    ) -> Result<Vec<AssetData>, Error> {
+        let fetched = self.fetch(at).await?;
+        finalize_allocation(fetched.assets, &self.config, strategy)
+    }
+}
+
+/// Resolves the wall clock time a run describes, used as the sheet snapshot date: the start
+/// of the week the points were read for in timestamp based runs, the pinned block's time
+/// otherwise.
+pub fn snapshot_time(at: Option<At>, block: &PinnedBlock) -> Result<DateTime<Local>, Error> {
+    let timestamp = match at {
+        Some(At::Timestamp(time)) => time / WEEK * WEEK,
+        _ => block.timestamp,
+    };
+    Ok(DateTime::from_timestamp(timestamp as i64, 0)
+        .ok_or_else(|| Error::decode(format!("timestamp {}", timestamp), "out of range"))?
+        .with_timezone(&Local))
+}
+
+/// Filters and normalises fetched weights, then adds the reserved slots and sorts by weight.
+pub fn finalize_allocation(
+    mut decoded_data: Vec<AssetData>,
+    config: &Config,
+    strategy: &dyn AllocationStrategy,
+) -> Result<Vec<AssetData>, Error> {
+    calculate_actual_weights(&mut decoded_data, config, strategy)?;
+    decoded_data.extend(config.reserved.iter().map(|slot| AssetData {
+        id: encode_asset_id(&slot.token, &slot.chain_id),
+        symbol: Some(slot.symbol.clone()),
+        symbol_source: SymbolSource::Chain,
+        token_addr: slot.token,
+        oft_address: *config.token_to_oft.get(&slot.token).unwrap_or(&slot.token),
+        chain_id: slot.chain_id,
+        actual_weight: Some(slot.weight as f64 / u16::MAX as f64),
+        converted_weight: Some(slot.weight),
+        weight: None,
+        relative_weight: None,
+    }));
+
+    decoded_data.sort_unstable_by_key(|x| (Reverse(x.converted_weight), x.id));
+    Ok(decoded_data)
+}
+
+/// Drops inactive and reserved assets and those below the minimum relative weight, then
+/// apportions what the reserved slots leave of `u16::MAX` exactly over the strategy's
+/// scores of the rest, within the configured caps.
+pub fn calculate_actual_weights(
+    asset_data: &mut Vec<AssetData>,
+    config: &Config,
+    strategy: &dyn AllocationStrategy,
+) -> Result<(), Error> {
+    // The strategies read `weight` and the filter `relative_weight`, both set by the fetch
+    if let Some(asset) = asset_data
+        .iter()
+        .find(|x| x.weight.is_none() || x.relative_weight.is_none())
+    {
+        return Err(Error::Allocation(format!(
+            "{} has no fetched weight",
+            asset.label()
+        )));
+    }
+    asset_data.retain(|asset| {
+        !config.inactive_assets.contains(&asset.token_addr)
+            && !config.is_reserved(&asset.token_addr, &asset.chain_id)
+            && asset.relative_weight >= Some(config.min_relative_weight)
+    });
+
+    let weights: Vec<(U256, U256)> = asset_data
+        .iter()
+        .map(|asset| asset.id)
+        .zip(strategy.scores(asset_data))
+        .collect();
+    let caps: Vec<u16> = asset_data
+        .iter()
+        .map(|asset| config.weight_cap(&asset.token_addr))
+        .collect();
+    let converted = capped_largest_remainder(&weights, &caps, u16::MAX - config.reserved_weight())
+        .ok_or_else(|| {
+            Error::Allocation(format!(
+                "no weight left to allocate over {} assets, or the caps are too tight to \
+                 allocate it",
+                asset_data.len()
+            ))
+        })?;
+
+    asset_data
+        .iter_mut()
+        .zip(converted)
+        .for_each(|(asset, converted_weight)| {
+            asset
+                .actual_weight
+                .replace(converted_weight as f64 / u16::MAX as f64);
+            asset.converted_weight.replace(converted_weight);
+        });
+    Ok(())
+}
+
+/// Quotes a CSV field holding a comma, quote or line break, doubling its quotes. Symbols
+/// are set by whoever deployed the token, so they can hold anything.
+pub(crate) fn csv_field(value: &str) -> Cow<'_, str> {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => Cow::Owned(format!("\"{}\"", value.replace('"', "\"\""))),
+        false => Cow::Borrowed(value),
+    }
+}
+
+/// `numerator / denominator` as a float, exact to 1e-28. `None` for a zero denominator.
+pub fn u256_division(numerator: &U256, denominator: &U256) -> Option<f64> {
+    if denominator.is_zero() {
+        return None;
+    }
+    let numerator = numerator.to::<U512>() * U512::from(SCALE);
+    let quotient = numerator / denominator.to::<U512>();
+    Some(quotient.to::<u128>() as f64 / SCALE as f64)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::{
+        allocation::Proportional,
+        mock_rpc::{Fixture, MockRpc},
+        snapshot::Snapshot,
+        turnover::parse_allocation_csv,
+    };
+    use alloy::{primitives::address, sol_types::SolCall};
+    #[test]
+    fn check_conversion() {
+        let num_one = U256::from(1000);
+        let num_two = U256::from(1000);
+        assert_eq!(u256_division(&num_one, &num_two), Some(1.));
+        assert_eq!(u256_division(&num_one, &U256::ZERO), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn fetches_and_allocates_offline() {
+        let fixture = Fixture::from_json(include_str!("../tests/fixtures/pipeline.json"));
+        let mut config = Config::from_toml(config::DEFAULT_CONFIG).unwrap();
+        let rpc = MockRpc::start(fixture.clone(), &config);
+        rpc.configure(&mut config);
+        config.symbol_cache = None;
+        let client = WeightClient::new(config.clone()).unwrap();
+
+        let fetched = client.fetch(None).await.unwrap();
+        assert_eq!(fetched.block, fixture.block);
+        assert_eq!(fetched.assets.len(), 5);
+        let allocation =
+            finalize_allocation(fetched.assets.clone(), &config, &Proportional).unwrap();
+
+        // The inactive asset and the one below min_relative_weight are dropped, JOOCE is
+        // reserved and the rest is split 500:300:150
+        let weight = |symbol: &str| {
+            allocation
+                .iter()
+                .find(|x| x.symbol.as_deref() == Some(symbol))
+                .and_then(|x| x.converted_weight)
+        };
+        assert_eq!(allocation.len(), 4);
+        assert_eq!(weight("PEPE"), Some(33802));
+        assert_eq!(weight("SHIB"), Some(20281));
+        assert_eq!(weight("MEW"), Some(10141));
+        assert_eq!(weight("JOOCE"), Some(1311));
+
+        // Every voting contract read is pinned to the fixture block
+        let block = serde_json::json!(format!("{:#x}", fixture.block.number));
+        let calls = rpc.calls.lock().unwrap().clone();
+        let voting_calls: Vec<_> = calls
+            .iter()
+            .filter(|x| x.to == config.voting_contract)
+            .collect();
+        assert!(
+            voting_calls
+                .iter()
+                .any(|x| x.selector == IJooceVoting::weightsSumCall::SELECTOR)
+        );
+        assert!(voting_calls.iter().all(|x| x.block == block));
+
+        // A contract without votes is an error, not a division by zero
+        let mut empty = config.clone();
+        let rpc = MockRpc::start(
+            Fixture {
+                weights_sum: Some(U256::ZERO),
+                ..fixture.clone()
+            },
+            &empty,
+        );
+        rpc.configure(&mut empty);
+        let err = WeightClient::new(empty)
+            .unwrap()
+            .fetch(None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::Allocation(_)));
+
+        let snapshot = Snapshot::new(
+            &config,
+            Proportional.name(),
+            fetched.block,
+            fetched.block.timestamp,
+            fetched.weights_sum,
+            fetched.assets,
+            allocation,
+        );
+        let mut csv = Vec::new();
+        snapshot.write_csv(&mut csv, &config).unwrap();
+        let previous = parse_allocation_csv(&String::from_utf8(csv).unwrap()).unwrap();
+        assert_eq!(previous.len(), 4);
+        assert_eq!(
+            previous
+                .iter()
+                .map(|x| x.converted_weight as u32)
+                .sum::<u32>(),
+            u16::MAX as u32
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn falls_back_when_a_symbol_rpc_is_down() {
+        let fixture = Fixture::from_json(include_str!("../tests/fixtures/pipeline.json"));
+        let mut config = Config::from_toml(config::DEFAULT_CONFIG).unwrap();
+        let rpc = MockRpc::start(fixture, &config);
+        rpc.configure(&mut config);
+        let cache = std::env::temp_dir().join(format!("jmx-symbols-{}.json", std::process::id()));
+        config.symbol_cache = Some(cache.clone());
+        let fetch = |config: &Config| {
+            let client = WeightClient::new(config.clone()).unwrap();
+            async move { client.fetch(None).await.unwrap().assets }
+        };
+
+        // A healthy run caches every symbol
+        let assets = fetch(&config).await;
+        assert!(assets.iter().all(|x| x.symbol_source.is_verified()));
+
+        // Ethereum's url refuses connections, the fallback url answers
+        let ethereum = U256::from(1);
+        config
+            .override_rpc_url(ethereum, "http://127.0.0.1:1".to_owned())
+            .unwrap();
+        config.chains.get_mut(&ethereum).unwrap().fallback_rpc_urls = vec![rpc.url.clone()];
+        let assets = fetch(&config).await;
+        assert!(assets.iter().all(|x| x.symbol_source.is_verified()));
+
+        // The fallback url alone is enough when the main one isn't set
+        config.chains.get_mut(&ethereum).unwrap().rpc_url = None;
+        let assets = fetch(&config).await;
+        assert!(assets.iter().all(|x| x.symbol_source.is_verified()));
+
+        // Without any url for Ethereum or a mint for MEW the override wins over the cache,
+        // and the other chains are unaffected
+        config
+            .chains
+            .get_mut(&ethereum)
+            .unwrap()
+            .fallback_rpc_urls
+            .clear();
+        let mew = address!("0x6A851667B20800988c0cE34276F63f86f085BB2c");
+        config.sol_mints.remove(&mew);
+        let shib = address!("0x95ad61b0a150d79219dcf64e1e6cc01f0b64c4ce");
+        config.symbol_overrides.insert(shib, "SHIB.E".to_owned());
+        let assets = fetch(&config).await;
+        std::fs::remove_file(&cache).unwrap();
+        let symbol = |token: Address| {
+            let asset = assets.iter().find(|x| x.token_addr == token).unwrap();
+            (asset.report_symbol(), asset.symbol_source)
+        };
+        assert_eq!(
+            symbol(address!("0x6982508145454ce325ddbe47a25d4ec3d2311933")),
+            ("PEPE*".to_owned(), SymbolSource::Cached)
+        );
+        assert_eq!(symbol(shib), ("SHIB.E*".to_owned(), SymbolSource::Override));
+        assert_eq!(symbol(mew), ("MEW*".to_owned(), SymbolSource::Cached));
+        assert_eq!(
+            symbol(address!("0x576e2bed8f7b46d34016198911cdf9886f78bea7")).1,
+            SymbolSource::Chain
+        );
+        let allocation = finalize_allocation(assets, &config, &Proportional).unwrap();
+        assert_eq!(allocation.len(), 4);
+    }
+}
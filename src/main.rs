@@ -1,189 +1,530 @@
-mod fetch_data;
-mod variables;
-mod write_data;
-use std::{env, time};
-
-use crate::{
-    IJooceVoting::IJooceVotingInstance,
-    fetch_data::{decode_asset_ids, get_ticker, get_weight},
-    variables::{
-        CHAIN_ID_TO_URL, INACTIVE_ASSETS, JOOCE_INT_WEIGHT, MIN_RELATIVE_WEIGHT, SCALE,
-        VOTING_CONTRACT_ADDRESS,
-    },
-    write_data::{print_hashmap, write_to_google_sheet},
+mod cli;
+use std::{
+    cmp::Reverse,
+    fs,
+    path::Path,
+    process::ExitCode,
+    time::{self, Duration},
 };
+
+use crate::cli::{Cli, ClientCommand, Command, OutputTarget, SnapshotArgs};
 use alloy::{
-    primitives::{Address, U256, U512, address},
-    providers::{DynProvider, Provider, ProviderBuilder},
+    eips::BlockId,
+    primitives::U256,
+    providers::{Provider, ProviderBuilder},
     signers::local::PrivateKeySigner,
-    sol,
 };
+use anyhow::Context;
+use chrono::Utc;
+use clap::Parser;
 use dotenv::dotenv;
-
+use jmx_weights::{
+    AssetData, Error, FetchedWeights, WeightClient,
+    allocation::{build_strategy, print_comparison},
+    checkpoint::{
+        SubmitOptions, confirm, print_simulation_report, print_submission_report,
+        select_stale_assets, simulate_checkpoints, submit_checkpoints,
+    },
+    config::Config,
+    fetch_data::{PinnedBlock, VOTING_CHAIN, decode_asset_ids, get_asset_ids, get_ticker},
+    finalize_allocation,
+    forecast::{forecast_weights, print_forecast},
+    indexer::{distinct_voters, index_events, open_database, votes_for_asset},
+    read_data::read_sheet_history,
+    safe::safe_batch,
+    snapshot::{Snapshot, latest_snapshot},
+    snapshot_time,
+    target::{ListMethod, ReadMethod, WeightsMethod, print_calldata, target_weights},
+    timeseries::{build_timeseries, write_timeseries_csv},
+    turnover::{
+        PreviousEntry, PreviousSource, apply_min_change, load_previous, print_turnover_report,
+    },
+    u256_division,
+    verify::{print_verification, verify_allocation},
+    voters::{get_voter_breakdown, print_voter_breakdown},
+    write_data::{
+        diff_allocations, get_sheets_hub, print_diff, print_hashmap, write_to_google_sheet,
+    },
+};
 use op_alloy_network::Optimism;
 
-#[derive(Debug, Clone)]
-pub struct AssetData {
-    id: U256,
-    token_addr: Address,
-    oft_address: Address,
-    symbol: Option<String>,
-    chain_id: U256,
-    relative_weight: Option<f64>,
-    actual_weight: Option<f64>,
-    converted_weight: Option<u16>,
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            // The innermost library error decides the code, anything else is a plain failure
+            let code = err
+                .chain()
+                .filter_map(|x| x.downcast_ref::<jmx_weights::Error>())
+                .last()
+                .map_or(1, |x| x.exit_code());
+            ExitCode::from(code)
+        }
+    }
 }
 
-sol!(
-    #[allow(missing_docs)]
-    #[sol(rpc)]
-    IJooceVoting,
-    "abi/JooceVoting.json"
-);
-
-sol!(
-    #[allow(missing_docs)]
-    #[sol(rpc)]
-    IErc20,
-    "abi/Erc20.json"
-);
-
-#[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn run() -> Result<(), anyhow::Error> {
     let time = time::Instant::now();
-    let args: Vec<String> = env::args().collect();
+    let cli = Cli::parse();
     dotenv().ok();
-    let pk: PrivateKeySigner = std::env::var("PRIVATE_KEY")
-        .expect("Private key missing")
-        .parse()?;
-    let key = op_alloy_network::EthereumWallet::new(pk);
-    let provider = ProviderBuilder::new_with_network::<Optimism>()
-        .wallet(key)
-        .connect_http(CHAIN_ID_TO_URL.get(&U256::from(8453)).unwrap().parse()?)
-        .erased();
-    let contract: IJooceVotingInstance<DynProvider<Optimism>, Optimism> =
-        IJooceVoting::new(VOTING_CONTRACT_ADDRESS.parse()?, provider.clone());
-    let asset_ids: Vec<U256> = contract.assets().call().await?;
-    if let Some(val) = args.get(1)
-        && val.to_lowercase() == "update"
-    {
-        update_relative_weight(&contract, &asset_ids).await;
+    let mut config = Config::load(cli.config.as_deref())?;
+    for (chain_id, url) in cli.rpc_overrides {
+        config.override_rpc_url(chain_id, url)?;
     }
-    let mut decoded_data = decode_asset_ids(&asset_ids);
-    let jooce = AssetData {
-        id: U256::default(),
-        symbol: Some("JOOCE".to_owned()),
-        token_addr: address!("0x100CE3E3391C00B6A52911313A4Ea8D23c8a38D8"),
-        oft_address: address!("0x100CE3E3391C00B6A52911313A4Ea8D23c8a38D8"),
-        chain_id: U256::from(8453),
-        actual_weight: Some(0.02),
-        converted_weight: Some(JOOCE_INT_WEIGHT),
-        relative_weight: None,
-    };
-    let total_weight = contract.weightsSum();
-
-    let (weights, symbols, total_weight) = tokio::join!(
-        get_weight(provider, &contract, &decoded_data),
-        get_ticker(&decoded_data),
-        total_weight.call()
-    );
-    let total_weight = total_weight.unwrap();
-    for (i, asset) in decoded_data.iter_mut().enumerate() {
-        asset.relative_weight = Some(u256_division(&weights[i], &total_weight));
-        asset.symbol = Some(symbols[i].clone());
+    if let Some(voting_contract) = cli.voting_contract {
+        config.voting_contract = voting_contract;
     }
-    println!("{:?}", decoded_data);
-    calculate_actual_weights(&mut decoded_data);
-    decoded_data.push(jooce);
-
-    decoded_data.sort_unstable_by(|a, b| {
-        b.converted_weight
-            .unwrap()
-            .cmp(&a.converted_weight.unwrap())
-    });
+    if let Some(cap) = cli.cap {
+        config.override_max_weight(cap)?;
+    }
+    let strategy = build_strategy(cli.strategy, &cli.strategy_args.options())?;
+    let strategy = strategy.as_ref();
 
-    write_to_google_sheet(&decoded_data).await;
-    print_hashmap(&decoded_data);
-    println!("{:?}", time.elapsed());
-    Ok(())
-}
+    match cli.command {
+        Command::Checkpoint {
+            assets,
+            all,
+            max_age,
+            dry_run,
+            yes,
+            from,
+            receipt_timeout,
+            max_bumps,
+            bump_percent,
+        } => {
+            let base_url = config.rpc_endpoint(&config.voting_chain_id)?;
+            let signer = match std::env::var("PRIVATE_KEY") {
+                Ok(val) => Some(val.parse::<PrivateKeySigner>()?),
+                Err(_) if dry_run => None,
+                Err(_) => anyhow::bail!("Private key missing"),
+            };
+            let from = match (from, &signer) {
+                (Some(val), _) => val,
+                (None, Some(signer)) => signer.address(),
+                (None, None) => anyhow::bail!("--dry-run needs PRIVATE_KEY or --from"),
+            };
+            let provider = match signer {
+                Some(signer) => ProviderBuilder::new_with_network::<Optimism>()
+                    .wallet(op_alloy_network::EthereumWallet::new(signer))
+                    .connect_http(base_url)
+                    .erased(),
+                None => ProviderBuilder::new_with_network::<Optimism>()
+                    .connect_http(base_url)
+                    .erased(),
+            };
+            let client = WeightClient::with_provider(config, provider);
+            let contract = client.contract();
+            let mut asset_ids = if assets.is_empty() {
+                get_asset_ids(contract, BlockId::latest()).await?
+            } else {
+                assets
+            };
+            if !all {
+                asset_ids = select_stale_assets(contract, &asset_ids, max_age).await?;
+                if asset_ids.is_empty() {
+                    println!("Every asset is current, nothing to checkpoint");
+                    return Ok(());
+                }
+            }
 
-async fn update_relative_weight(
-    contract: &IJooceVotingInstance<DynProvider<Optimism>, Optimism>,
-    ids: &[U256],
-) {
-    for id in ids.iter() {
-        println!("{}", id);
-        let tx = contract.checkpointAsset(*id).send().await;
-        match tx {
-            Ok(val) => {
-                let receipt = val.get_receipt().await;
-                println!("{:?}", receipt.unwrap())
+            let simulations = simulate_checkpoints(contract, from, &asset_ids).await;
+            print_simulation_report(contract, &simulations).await?;
+            if dry_run {
+                return Ok(());
+            }
+            let passing: Vec<(U256, u64)> = simulations
+                .iter()
+                .filter_map(|x| Some((x.id, x.gas?)))
+                .collect();
+            if passing.is_empty() {
+                println!("Nothing to checkpoint");
+            } else if yes || confirm(&format!("Send {} checkpoint transactions?", passing.len())) {
+                let options = SubmitOptions {
+                    receipt_timeout: Duration::from_secs(receipt_timeout),
+                    max_bumps,
+                    bump_percent,
+                };
+                // clap only accepts --from with --dry-run, so this is the PRIVATE_KEY address
+                let outcomes = submit_checkpoints(contract, from, &passing, options).await?;
+                print_submission_report(&outcomes);
+            } else {
+                println!("Aborted");
             }
-            Err(val) => {
-                println!("Error with tx - {}", val);
+        }
+        Command::History { limit } => {
+            let hub = get_sheets_hub().await?;
+            for snapshot in read_sheet_history(&hub, limit).await? {
+                println!(
+                    "Tab {} ({})",
+                    snapshot.title,
+                    snapshot.snapshot_date.as_deref().unwrap_or("no date")
+                );
+                for row in snapshot.rows {
+                    println!(
+                        "  {:<12} {:>8.4}% {:>6} {}",
+                        row.symbol,
+                        row.percentage * 100.,
+                        row.converted_weight,
+                        row.chain
+                    );
+                }
             }
         }
+        Command::Client(command) => {
+            let client = WeightClient::new(config.clone())?;
+            let (provider, contract) = (client.provider(), client.contract());
+            match command {
+                ClientCommand::Fetch { at } => {
+                    let fetched = client.fetch(at).await?;
+                    for asset in fetched.assets.iter() {
+                        println!(
+                            "{:<12} {:<10} {:>8.4}% {:>28} {}",
+                            asset.report_symbol(),
+                            config.chain_name(&asset.chain_id),
+                            asset.relative_weight.unwrap_or_default() * 100.,
+                            asset.weight.unwrap_or_default(),
+                            asset.id
+                        );
+                    }
+                    if cli.verbose {
+                        println!("{:#?}", fetched.assets);
+                    }
+                    println!(
+                        "weightsSum {} at block {} ({})",
+                        fetched.weights_sum, fetched.block.number, fetched.block.hash
+                    );
+                }
+                ClientCommand::Compute {
+                    at,
+                    turnover,
+                    snapshot,
+                } => {
+                    let FetchedWeights {
+                        assets: fetched,
+                        weights_sum,
+                        block,
+                    } = client.fetch(at).await?;
+                    let mut asset_data = finalize_allocation(fetched.clone(), &config, strategy)?;
+                    if let Some(source) = &turnover.previous {
+                        let previous = load_previous(source).await?;
+                        damp_turnover(&mut asset_data, &config, &previous, turnover.min_change)?;
+                        print_diff(&diff_allocations(&asset_data, &fetched, &previous, &config));
+                    }
+                    if cli.verbose {
+                        println!("{:#?}", asset_data);
+                    }
+                    print_hashmap(&asset_data)?;
+                    if !snapshot.no_snapshot {
+                        let snapshot_data = Snapshot::new(
+                            &config,
+                            strategy.name(),
+                            block,
+                            snapshot_time(at, &block)?.timestamp() as u64,
+                            weights_sum,
+                            fetched,
+                            asset_data,
+                        );
+                        write_snapshot(&snapshot_data, &config, &snapshot)?;
+                    }
+                }
+                ClientCommand::Export {
+                    output,
+                    safe_batch,
+                    calldata,
+                    at,
+                    turnover,
+                    snapshot,
+                } => {
+                    let FetchedWeights {
+                        assets: fetched,
+                        weights_sum,
+                        block,
+                    } = client.fetch(at).await?;
+                    let mut asset_data = finalize_allocation(fetched.clone(), &config, strategy)?;
+                    // A new tab is always diffed against the newest one
+                    let source = match (&turnover.previous, output) {
+                        (Some(source), _) => Some(source.clone()),
+                        (None, OutputTarget::Stdout) => None,
+                        (None, _) => Some(PreviousSource::Sheet),
+                    };
+                    let mut diff = None;
+                    if let Some(source) = source {
+                        let previous = load_previous(&source).await?;
+                        if turnover.previous.is_some() {
+                            damp_turnover(
+                                &mut asset_data,
+                                &config,
+                                &previous,
+                                turnover.min_change,
+                            )?;
+                        }
+                        let changes = diff_allocations(&asset_data, &fetched, &previous, &config);
+                        print_diff(&changes);
+                        diff = Some(changes);
+                    }
+                    if output != OutputTarget::Stdout {
+                        let snapshot_time = snapshot_time(at, &block)?;
+                        write_to_google_sheet(
+                            &asset_data,
+                            &config,
+                            snapshot_time,
+                            &block,
+                            diff.as_ref(),
+                        )
+                        .await?;
+                    }
+                    if output != OutputTarget::Sheet {
+                        print_hashmap(&asset_data)?;
+                    }
+                    if let Some(path) = safe_batch {
+                        write_safe_batch(&path, &asset_data, &config, &block)?;
+                    }
+                    if calldata {
+                        let target = config.target()?;
+                        let method = WeightsMethod::load(target)?;
+                        let weights = target_weights(&asset_data, target.order)?;
+                        print_calldata(target, &method, &weights)?;
+                    }
+                    if !snapshot.no_snapshot {
+                        let snapshot_data = Snapshot::new(
+                            &config,
+                            strategy.name(),
+                            block,
+                            snapshot_time(at, &block)?.timestamp() as u64,
+                            weights_sum,
+                            fetched,
+                            asset_data,
+                        );
+                        write_snapshot(&snapshot_data, &config, &snapshot)?;
+                    }
+                }
+                ClientCommand::Compare { strategies, at } => {
+                    let decoded_data = client.fetch(at).await?.assets;
+                    let mut names = Vec::new();
+                    let mut allocations = Vec::new();
+                    for kind in strategies {
+                        let strategy = build_strategy(kind, &cli.strategy_args.options())?;
+                        names.push(strategy.name());
+                        allocations.push(finalize_allocation(
+                            decoded_data.clone(),
+                            &config,
+                            strategy.as_ref(),
+                        )?);
+                    }
+                    print_comparison(&names, &allocations);
+                }
+                ClientCommand::Verify {
+                    expected,
+                    snapshot_dir,
+                } => {
+                    let source = match expected {
+                        Some(source) => source,
+                        None => PreviousSource::File(latest_snapshot(&snapshot_dir)?),
+                    };
+                    match &source {
+                        PreviousSource::Sheet => println!("Verifying the newest sheet tab"),
+                        PreviousSource::File(path) => println!("Verifying {}", path.display()),
+                    }
+                    let expected = load_previous(&source).await?;
+                    let target = config.target()?;
+                    let method = ReadMethod::load(target)?;
+                    let list_method = ListMethod::load(target)?;
+                    let verification = verify_allocation(
+                        &config,
+                        target,
+                        &method,
+                        list_method.as_ref(),
+                        &expected,
+                    )
+                    .await?;
+                    print_verification(&verification);
+                    if !verification.mismatches.is_empty() {
+                        anyhow::bail!(
+                            "{} of {} weights on {} differ from the expected allocation",
+                            verification.mismatches.len(),
+                            verification.checked,
+                            target.address
+                        );
+                    }
+                }
+                ClientCommand::Timeseries {
+                    from,
+                    to,
+                    step_weeks,
+                    out,
+                } => {
+                    let to = to.unwrap_or_else(|| Utc::now().timestamp() as u64);
+                    let rows = build_timeseries(
+                        provider, contract, &config, strategy, from, to, step_weeks,
+                    )
+                    .await?;
+                    match out {
+                        Some(path) => {
+                            write_timeseries_csv(&rows, std::fs::File::create(&path)?)?;
+                            println!("Wrote {} rows to {}", rows.len(), path.display());
+                        }
+                        None => write_timeseries_csv(&rows, std::io::stdout().lock())?,
+                    }
+                }
+                ClientCommand::Index {
+                    db,
+                    from_block,
+                    chunk_size,
+                    confirmations,
+                } => {
+                    let mut db = open_database(&db)?;
+                    let from_block = from_block.or(config.voting_deploy_block);
+                    index_events(contract, &mut db, from_block, chunk_size, confirmations).await?;
+                }
+                ClientCommand::Votes { db, asset, limit } => {
+                    let db = open_database(&db)?;
+                    for vote in votes_for_asset(&db, config.voting_contract, asset, limit)? {
+                        println!(
+                            "block {:<10} asset {} {} weight {:>6} tx {}",
+                            vote.block_number, vote.asset_id, vote.user, vote.weight, vote.tx_hash
+                        );
+                    }
+                }
+                ClientCommand::Voters { voters, db, top } => {
+                    let voters = if voters.is_empty() {
+                        distinct_voters(&open_database(&db)?, config.voting_contract)?
+                    } else {
+                        voters
+                    };
+                    anyhow::ensure!(
+                        !voters.is_empty(),
+                        "No voters given and none indexed in {}, run `index` first",
+                        db.display()
+                    );
+                    let (breakdown, powers) =
+                        get_voter_breakdown(provider, contract, &config, &voters).await?;
+                    print_voter_breakdown(&breakdown, &powers, &config, top);
+                }
+                ClientCommand::Forecast { weeks, threshold } => {
+                    let (current, forecast) = tokio::try_join!(
+                        client.allocate(strategy, None),
+                        forecast_weights(provider, contract, &config, strategy, weeks)
+                    )?;
+                    print_forecast(&current, &forecast, threshold);
+                }
+                ClientCommand::InspectAsset { asset_id } => {
+                    inspect_asset(&client, asset_id).await?;
+                }
+            }
+        }
+    }
+    if cli.verbose {
+        println!("Done in {:.1?}", time.elapsed());
     }
+    Ok(())
 }
 
-fn calculate_actual_weights(asset_data: &mut Vec<AssetData>) {
-    asset_data.retain(|asset| {
-        !INACTIVE_ASSETS.contains(&asset.token_addr)
-            && asset.relative_weight.unwrap() >= MIN_RELATIVE_WEIGHT
-    });
-
-    let weight_sum = asset_data
-        .iter()
-        .fold(0., |acc, x| acc + x.relative_weight.unwrap());
-
-    asset_data.iter_mut().for_each(|asset| {
-        let relative_weight = asset.relative_weight.unwrap();
-        let actual_weight = (relative_weight / weight_sum) * 0.98;
-        let converted_weight = (u16::MAX as f64 * actual_weight) as u16;
-        asset.actual_weight.replace(actual_weight);
-        asset.converted_weight.replace(converted_weight);
-    });
-    let adjusted_sum = asset_data
-        .iter()
-        .fold(0, |acc, x| acc + x.converted_weight.unwrap());
-
-    let remainder = u16::MAX - JOOCE_INT_WEIGHT - adjusted_sum;
-    let base = remainder / asset_data.len() as u16;
-    let extra = remainder % asset_data.len() as u16;
-
-    asset_data.iter_mut().for_each(|asset| {
-        asset
-            .converted_weight
-            .replace(asset.converted_weight.unwrap() + base);
-    });
+fn write_safe_batch(
+    path: &Path,
+    allocation: &[AssetData],
+    config: &Config,
+    block: &PinnedBlock,
+) -> Result<(), anyhow::Error> {
+    let target = config.target()?;
+    let method = WeightsMethod::load(target)?;
+    let weights = target_weights(allocation, target.order)?;
+    let description = format!(
+        "{} of {} at Base block {}, config {}",
+        method.function.signature(),
+        target.address,
+        block.number,
+        config.source_hash
+    );
+    let batch = safe_batch(
+        target,
+        &method,
+        &weights,
+        Utc::now().timestamp_millis(),
+        description,
+    );
+    let file =
+        fs::File::create(path).with_context(|| format!("Can't create {}", path.display()))?;
+    serde_json::to_writer_pretty(file, &batch)?;
+    println!("Wrote Safe batch {}", path.display());
+    Ok(())
+}
 
-    asset_data
-        .iter_mut()
-        .take(extra as usize)
-        .for_each(|asset| {
-            asset
-                .converted_weight
-                .replace(asset.converted_weight.unwrap() + 1);
-        });
+fn write_snapshot(
+    snapshot: &Snapshot,
+    config: &Config,
+    args: &SnapshotArgs,
+) -> Result<(), anyhow::Error> {
+    let (json_path, csv_path) = snapshot.write(&args.snapshot_dir, config)?;
+    println!(
+        "Wrote snapshot {} and {}",
+        json_path.display(),
+        csv_path.display()
+    );
+    Ok(())
 }
 
-fn u256_division(numerator: &U256, denominator: &U256) -> f64 {
-    let numerator = numerator.to::<U512>() * U512::from(SCALE);
-    println!("{}", numerator);
-    let quotient = numerator / denominator.to::<U512>();
-    println!("{}", quotient);
-    quotient.to::<u128>() as f64 / SCALE as f64
+/// Keeps the weights that moved by less than `min_change` of the index since the previous
+/// allocation and prints the turnover.
+fn damp_turnover(
+    asset_data: &mut [AssetData],
+    config: &Config,
+    previous: &[PreviousEntry],
+    min_change: f64,
+) -> Result<(), anyhow::Error> {
+    let min_change_units = (min_change * u16::MAX as f64).round() as u16;
+    let report = apply_min_change(asset_data, previous, config, min_change_units)?;
+    asset_data.sort_unstable_by_key(|x| (Reverse(x.converted_weight), x.id));
+    print_turnover_report(&report, min_change);
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
+async fn inspect_asset(client: &WeightClient, asset_id: U256) -> Result<(), anyhow::Error> {
+    let (contract, config) = (client.contract(), client.config());
+    let asset_data = decode_asset_ids(&[asset_id], config);
+    let asset = &asset_data[0];
+    let weight = contract.weight(asset_id);
+    let total_weight = contract.weightsSum();
+    let (weight, total_weight, symbols) = tokio::join!(
+        weight.call(),
+        total_weight.call(),
+        get_ticker(&asset_data, config)
+    );
+    let weight =
+        weight.map_err(|e| Error::rpc(VOTING_CHAIN, format!("weight of asset {}", asset_id), e))?;
+    let total_weight = total_weight.map_err(|e| Error::rpc(VOTING_CHAIN, "weightsSum", e))?;
+    // `None` while nobody has voted
+    let relative_weight = u256_division(&weight, &total_weight);
 
-    use super::*;
-    #[test]
-    fn check_conversion() {
-        let num_one = U256::from(1000);
-        let num_two = U256::from(1000);
-        let result = u256_division(&num_one, &num_two);
+    println!("Asset id:         {}", asset.id);
+    println!(
+        "Symbol:           {}{}",
+        symbols[0].symbol.as_deref().unwrap_or("-"),
+        match symbols[0].source.is_verified() {
+            true => "",
+            false => " (unverified)",
+        }
+    );
+    println!(
+        "Chain:            {} ({})",
+        config.chain_name(&asset.chain_id),
+        asset.chain_id
+    );
+    println!("Token address:    {}", asset.token_addr);
+    println!("OFT address:      {}", asset.oft_address);
+    println!("Weight:           {}", weight);
+    match relative_weight {
+        Some(relative_weight) => println!("Relative weight:  {:.4}%", relative_weight * 100.),
+        None => println!("Relative weight:  - (weightsSum is 0)"),
     }
+    println!(
+        "Inactive:         {}",
+        config.inactive_assets.contains(&asset.token_addr)
+    );
+    println!(
+        "Below minimum:    {}",
+        relative_weight.is_none_or(|x| x < config.min_relative_weight)
+    );
+    Ok(())
 }
@@ -0,0 +1,325 @@
+//! In-process JSON-RPC backend for offline tests. It answers the EVM calls the pipeline
+//! makes, `assets`, `weight` and `weightsSum` of the voting contract and ERC-20 `symbol`,
+//! directly or through Multicall3 `aggregate`/`aggregate3`, and Solana `getMultipleAccounts`
+//! for the token metadata accounts. Every chain is served from the same url.
+
+use crate::{
+    IErc20, IJooceVoting,
+    config::Config,
+    fetch_data::{PinnedBlock, encode_asset_id},
+    variables::SOLANA_CHAIN_ID,
+};
+use alloy::{
+    primitives::{Address, Bytes, U256},
+    providers::{
+        MULTICALL3_ADDRESS,
+        bindings::IMulticall3::{self, aggregate3Call, aggregateCall},
+    },
+    sol_types::{SolCall, SolValue},
+};
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use solana_sdk::pubkey::Pubkey;
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// State of the chains the fake backend serves, loaded from JSON.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fixture {
+    pub block: PinnedBlock,
+    pub assets: Vec<FixtureAsset>,
+    /// Defaults to the sum of the asset weights
+    pub weights_sum: Option<U256>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FixtureAsset {
+    pub token: Address,
+    pub chain_id: u64,
+    pub symbol: String,
+    pub weight: U256,
+}
+
+impl Fixture {
+    pub fn from_json(contents: &str) -> Fixture {
+        serde_json::from_str(contents).expect("Invalid fixture")
+    }
+
+    pub fn asset_id(&self, asset: &FixtureAsset) -> U256 {
+        encode_asset_id(&asset.token, &U256::from(asset.chain_id))
+    }
+}
+
+/// One `eth_call` the backend answered, unrolled from multicalls.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedCall {
+    pub to: Address,
+    pub selector: [u8; 4],
+    /// Block parameter of the request, `latest` when missing
+    pub block: Value,
+}
+
+pub struct MockRpc {
+    pub url: String,
+    pub calls: Arc<Mutex<Vec<RecordedCall>>>,
+}
+
+impl MockRpc {
+    /// Serves `fixture` on a free local port until the test process exits.
+    pub fn start(fixture: Fixture, config: &Config) -> MockRpc {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(State::new(fixture, config));
+        let calls = state.calls.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = state.clone();
+                thread::spawn(move || serve(stream, &state));
+            }
+        });
+        MockRpc { url, calls }
+    }
+
+    /// Points every chain of `config` at the backend.
+    pub fn configure(&self, config: &mut Config) {
+        let chain_ids: Vec<U256> = config.chains.keys().copied().collect();
+        for chain_id in chain_ids {
+            config.override_rpc_url(chain_id, self.url.clone()).unwrap();
+        }
+    }
+}
+
+struct State {
+    fixture: Fixture,
+    voting_contract: Address,
+    /// Metadata PDA => symbol of the Solana assets
+    metadata: HashMap<Pubkey, String>,
+    calls: Arc<Mutex<Vec<RecordedCall>>>,
+}
+
+impl State {
+    fn new(fixture: Fixture, config: &Config) -> State {
+        let metadata = fixture
+            .assets
+            .iter()
+            .filter(|x| U256::from(x.chain_id) == SOLANA_CHAIN_ID)
+            .map(|x| {
+                let mint = config.sol_mints[&x.token];
+                let pda = mpl_token_metadata::accounts::Metadata::find_pda(&mint).0;
+                (pda, x.symbol.clone())
+            })
+            .collect();
+        State {
+            fixture,
+            voting_contract: config.voting_contract,
+            metadata,
+            calls: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn handle(&self, request: &Value) -> Value {
+        let params = &request["params"];
+        let result = match request["method"].as_str().unwrap_or_default() {
+            "eth_chainId" => Ok(json!("0x2105")),
+            "eth_blockNumber" => Ok(json!(format!("{:#x}", self.fixture.block.number))),
+            "eth_getBlockByNumber" | "eth_getBlockByHash" => Ok(self.block()),
+            "eth_call" => {
+                let data: Bytes = serde_json::from_value(
+                    params[0]
+                        .get("input")
+                        .or(params[0].get("data"))
+                        .cloned()
+                        .unwrap_or(json!("0x")),
+                )
+                .unwrap();
+                let to: Address = serde_json::from_value(params[0]["to"].clone()).unwrap();
+                let block = params.get(1).cloned().unwrap_or(json!("latest"));
+                self.call(to, &data, &block).map(|x| json!(Bytes::from(x)))
+            }
+            "getMultipleAccounts" => Ok(self.accounts(&params[0])),
+            method => Err(json!({"code": -32601, "message": format!("{} not mocked", method)})),
+        };
+        match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+            Err(error) => json!({"jsonrpc": "2.0", "id": request["id"], "error": error}),
+        }
+    }
+
+    fn block(&self) -> Value {
+        let block = &self.fixture.block;
+        let mut out = alloy::rpc::types::Block::<alloy::rpc::types::Transaction>::default();
+        out.header.hash = block.hash;
+        out.header.inner.number = block.number;
+        out.header.inner.timestamp = block.timestamp;
+        serde_json::to_value(out).unwrap()
+    }
+
+    fn call(&self, to: Address, data: &[u8], block: &Value) -> Result<Vec<u8>, Value> {
+        let revert = || json!({"code": 3, "message": "execution reverted", "data": "0x"});
+        let selector: [u8; 4] = data.get(..4).ok_or_else(revert)?.try_into().unwrap();
+        self.calls.lock().unwrap().push(RecordedCall {
+            to,
+            selector,
+            block: block.clone(),
+        });
+
+        if to == MULTICALL3_ADDRESS {
+            return match selector {
+                aggregateCall::SELECTOR => {
+                    let calls = aggregateCall::abi_decode(data).map_err(|_| revert())?.calls;
+                    let mut returned = Vec::new();
+                    for call in calls {
+                        returned.push(Bytes::from(self.call(
+                            call.target,
+                            &call.callData,
+                            block,
+                        )?));
+                    }
+                    Ok((U256::from(self.fixture.block.number), returned).abi_encode_params())
+                }
+                aggregate3Call::SELECTOR => {
+                    let calls = aggregate3Call::abi_decode(data)
+                        .map_err(|_| revert())?
+                        .calls;
+                    let returned: Vec<IMulticall3::Result> = calls
+                        .into_iter()
+                        .map(|call| {
+                            let result = self.call(call.target, &call.callData, block);
+                            IMulticall3::Result {
+                                success: result.is_ok(),
+                                returnData: result.unwrap_or_default().into(),
+                            }
+                        })
+                        .collect();
+                    Ok(aggregate3Call::abi_encode_returns(&returned))
+                }
+                _ => Err(revert()),
+            };
+        }
+
+        let assets = &self.fixture.assets;
+        if to == self.voting_contract {
+            return match selector {
+                IJooceVoting::assetsCall::SELECTOR => {
+                    let ids: Vec<U256> = assets.iter().map(|x| self.fixture.asset_id(x)).collect();
+                    Ok(IJooceVoting::assetsCall::abi_encode_returns(&ids))
+                }
+                IJooceVoting::weightCall::SELECTOR => {
+                    let id = IJooceVoting::weightCall::abi_decode(data)
+                        .map_err(|_| revert())?
+                        .assetId;
+                    let asset = assets
+                        .iter()
+                        .find(|x| self.fixture.asset_id(x) == id)
+                        .ok_or_else(revert)?;
+                    Ok(IJooceVoting::weightCall::abi_encode_returns(&asset.weight))
+                }
+                IJooceVoting::weightsSumCall::SELECTOR => {
+                    let sum = self
+                        .fixture
+                        .weights_sum
+                        .unwrap_or_else(|| assets.iter().map(|x| x.weight).sum());
+                    Ok(IJooceVoting::weightsSumCall::abi_encode_returns(&sum))
+                }
+                _ => Err(revert()),
+            };
+        }
+        match (selector, assets.iter().find(|x| x.token == to)) {
+            (IErc20::symbolCall::SELECTOR, Some(asset)) => {
+                Ok(IErc20::symbolCall::abi_encode_returns(&asset.symbol))
+            }
+            _ => Err(revert()),
+        }
+    }
+
+    fn accounts(&self, pubkeys: &Value) -> Value {
+        let accounts: Vec<Value> = pubkeys
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .iter()
+            .map(|x| {
+                let pubkey: Pubkey = x.as_str().unwrap().parse().unwrap();
+                match self.metadata.get(&pubkey) {
+                    Some(symbol) => {
+                        let data = metadata_account(symbol);
+                        json!({
+                            "lamports": 1_000_000,
+                            "data": [base64::engine::general_purpose::STANDARD.encode(&data), "base64"],
+                            "owner": mpl_token_metadata::ID.to_string(),
+                            "executable": false,
+                            "rentEpoch": 0,
+                            "space": data.len(),
+                        })
+                    }
+                    None => Value::Null,
+                }
+            })
+            .collect();
+        json!({"context": {"slot": 1}, "value": accounts})
+    }
+}
+
+/// Borsh layout of a V1 token metadata account with only the symbol set.
+fn metadata_account(symbol: &str) -> Vec<u8> {
+    let mut data = vec![4u8]; // Key::MetadataV1
+    data.extend([0u8; 64]); // update authority and mint
+    for field in ["", symbol, ""] {
+        data.extend((field.len() as u32).to_le_bytes());
+        data.extend(field.as_bytes());
+    }
+    data.extend(0u16.to_le_bytes()); // seller fee basis points
+    data.extend([0, 0, 1]); // no creators, not sold, mutable
+    data.extend([0; 6]); // the optional fields
+    data
+}
+
+/// Reads HTTP/1.1 requests off a keep-alive connection and answers each one.
+fn serve(stream: TcpStream, state: &State) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let mut length = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+
+        let request: Value = serde_json::from_slice(&body).unwrap();
+        let response = match request.as_array() {
+            Some(batch) => Value::Array(batch.iter().map(|x| state.handle(x)).collect()),
+            None => state.handle(&request),
+        };
+        let response = response.to_string();
+        let written = write!(
+            writer,
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if written.is_err() {
+            return;
+        }
+    }
+}
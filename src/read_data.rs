@@ -0,0 +1,107 @@
+use crate::{
+    error::Error,
+    write_data::{SheetsHub, sheet_error, spreadsheet_id},
+};
+use alloy::primitives::Address;
+use serde_json::Value;
+
+#[derive(Debug, Clone)]
+pub struct SheetRow {
+    pub symbol: String,
+    pub percentage: f64,
+    pub converted_weight: u16,
+    pub chain: String,
+    /// Missing on tabs written before the column was added
+    pub oft_address: Option<Address>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SheetSnapshot {
+    pub title: String,
+    pub snapshot_date: Option<String>,
+    pub rows: Vec<SheetRow>,
+}
+
+/// Reads the last `limit` tabs of the spreadsheet, newest first.
+pub async fn read_sheet_history(
+    hub: &SheetsHub,
+    limit: usize,
+) -> Result<Vec<SheetSnapshot>, Error> {
+    let spreadsheet_id = spreadsheet_id()?;
+    let sheets = hub
+        .spreadsheets()
+        .get(spreadsheet_id.as_str())
+        .doit()
+        .await
+        .map_err(sheet_error("Reading the tabs"))?
+        .1
+        .sheets
+        .unwrap_or_default();
+
+    let titles: Vec<String> = sheets
+        .iter()
+        .rev()
+        .take(limit)
+        .filter_map(|sheet| sheet.properties.as_ref()?.title.clone())
+        .collect();
+    if titles.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut call = hub
+        .spreadsheets()
+        .values_batch_get(spreadsheet_id.as_str())
+        .value_render_option("UNFORMATTED_VALUE")
+        .date_time_render_option("FORMATTED_STRING");
+    for title in titles.iter() {
+        call = call.add_ranges(&format!("'{}'!A1:G50", title));
+    }
+    let value_ranges = call
+        .doit()
+        .await
+        .map_err(sheet_error(&format!("Reading tabs {}", titles.join(", "))))?
+        .1
+        .value_ranges
+        .unwrap_or_default();
+
+    Ok(titles
+        .into_iter()
+        .zip(value_ranges)
+        .map(|(title, range)| parse_snapshot(title, range.values.unwrap_or_default()))
+        .collect())
+}
+
+fn parse_snapshot(title: String, values: Vec<Vec<Value>>) -> SheetSnapshot {
+    let snapshot_date = values
+        .first()
+        .and_then(|header| header.get(6))
+        .map(cell_to_string);
+
+    let rows = values
+        .iter()
+        .skip(1)
+        .take_while(|row| row.first().map(cell_to_string).as_deref() != Some("Checks"))
+        .filter_map(|row| {
+            Some(SheetRow {
+                symbol: cell_to_string(row.first()?),
+                percentage: row.get(1)?.as_f64()?,
+                converted_weight: row.get(2)?.as_f64()? as u16,
+                chain: row.get(3).map(cell_to_string).unwrap_or_default(),
+                oft_address: row.get(4).and_then(|x| cell_to_string(x).parse().ok()),
+            })
+        })
+        .collect();
+
+    SheetSnapshot {
+        title,
+        snapshot_date,
+        rows,
+    }
+}
+
+fn cell_to_string(value: &Value) -> String {
+    match value {
+        Value::String(val) => val.clone(),
+        val => val.to_string(),
+    }
+}
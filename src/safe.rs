@@ -0,0 +1,148 @@
+use crate::{
+    config::Target,
+    target::{WeightsMethod, format_value},
+};
+use alloy::{
+    json_abi::{Param, StateMutability},
+    primitives::{Address, Bytes},
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Version of the Safe Transaction Builder the batch files are written for.
+const TX_BUILDER_VERSION: &str = "1.16.5";
+
+/// Batch file the Safe Transaction Builder app imports.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeBatch {
+    pub version: String,
+    pub chain_id: String,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    pub meta: BatchMeta,
+    pub transactions: Vec<BatchTransaction>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchMeta {
+    pub name: String,
+    pub description: String,
+    pub tx_builder_version: String,
+    pub created_from_safe_address: String,
+    pub created_from_owner_address: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchTransaction {
+    pub to: Address,
+    pub value: String,
+    /// Left empty, the app encodes the call from the method and its inputs
+    pub data: Option<Bytes>,
+    pub contract_method: ContractMethod,
+    pub contract_inputs_values: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ContractMethod {
+    pub inputs: Vec<Param>,
+    pub name: String,
+    pub payable: bool,
+}
+
+/// Builds a batch with a single call setting `weights` on the target contract. Arguments are
+/// written the way the app exports them, `[a,b,c]`.
+pub fn safe_batch(
+    target: &Target,
+    method: &WeightsMethod,
+    weights: &[(Address, u16)],
+    created_at: i64,
+    description: String,
+) -> SafeBatch {
+    // The app reads tuples as nested arrays
+    let inputs = method
+        .input_names()
+        .into_iter()
+        .zip(method.arguments(weights).iter())
+        .map(|(name, value)| (name, format_value(value, ("[", "]"))))
+        .collect();
+
+    SafeBatch {
+        version: "1.0".to_owned(),
+        chain_id: target.chain_id.to_string(),
+        created_at,
+        meta: BatchMeta {
+            name: format!("JMX rebalance, {} assets", weights.len()),
+            description,
+            tx_builder_version: TX_BUILDER_VERSION.to_owned(),
+            created_from_safe_address: target.safe.map(|x| x.to_checksum(None)).unwrap_or_default(),
+            created_from_owner_address: String::new(),
+        },
+        transactions: vec![BatchTransaction {
+            to: target.address,
+            value: "0".to_owned(),
+            data: None,
+            contract_method: ContractMethod {
+                inputs: method.function.inputs.clone(),
+                name: method.function.name.clone(),
+                payable: method.function.state_mutability == StateMutability::Payable,
+            },
+            contract_inputs_values: inputs,
+        }],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AssetData, config::WeightOrder, target::target_weights};
+    use alloy::{json_abi::JsonAbi, primitives::U256};
+    use std::path::PathBuf;
+
+    #[test]
+    fn builds_sorted_batches() {
+        let abi: JsonAbi =
+            serde_json::from_str(include_str!("../tests/fixtures/target_abi.json")).unwrap();
+        let method = WeightsMethod::from_abi(&abi, "setWeights").unwrap();
+        let target = Target {
+            address: Address::repeat_byte(0xaa),
+            chain_id: U256::from(8453),
+            safe: Some(Address::repeat_byte(0xbb)),
+            abi: PathBuf::from("tests/fixtures/target_abi.json"),
+            method: "setWeights".to_owned(),
+            read_method: Some("weights".to_owned()),
+            list_method: Some("assets".to_owned()),
+            order: WeightOrder::Address,
+        };
+        let asset = |i: u8, converted_weight: u16| AssetData::test_asset(i, Some(converted_weight));
+        let allocation = vec![asset(3, 40000), asset(1, 20000), asset(2, 5535)];
+
+        let weights = target_weights(&allocation, WeightOrder::Address).unwrap();
+        let batch = safe_batch(&target, &method, &weights, 0, String::new());
+        let values = &batch.transactions[0].contract_inputs_values;
+        assert_eq!(
+            values["assets"],
+            format!(
+                "[{},{},{}]",
+                Address::repeat_byte(1),
+                Address::repeat_byte(2),
+                Address::repeat_byte(3)
+            )
+        );
+        assert_eq!(values["weights"], "[20000,5535,40000]");
+
+        let by_weight = target_weights(&allocation, WeightOrder::Weight).unwrap();
+        assert_eq!(by_weight[0], (Address::repeat_byte(3), 40000));
+
+        // The app needs the ABI types of the inputs
+        let json = serde_json::to_value(&batch).unwrap();
+        assert_eq!(json["chainId"], "8453");
+        assert_eq!(
+            json["transactions"][0]["contractMethod"]["inputs"][1]["type"],
+            "uint16[]"
+        );
+        assert!(WeightsMethod::from_abi(&abi, "weights").is_err());
+    }
+}
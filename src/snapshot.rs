@@ -0,0 +1,245 @@
+use crate::{
+    AssetData,
+    config::Config,
+    csv_field,
+    error::{BoxError, Error},
+    fetch_data::PinnedBlock,
+};
+use alloy::primitives::{Address, B256, U256};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Schema version of the snapshot files written by this build.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Everything a run used and produced, written as JSON and CSV next to each other.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub tool_version: String,
+    /// keccak256 of the config file the run loaded
+    pub config_hash: B256,
+    pub voting_contract: Address,
+    pub strategy: String,
+    /// Base block every voting contract read was pinned to
+    pub block: PinnedBlock,
+    /// Time the allocation describes, the block's unless rebuilt at a past timestamp
+    pub timestamp: u64,
+    pub weights_sum: U256,
+    /// Every asset of the voting contract with its raw weight, before the filters
+    pub fetched: Vec<AssetData>,
+    /// Final allocation, reserved slots included
+    pub allocation: Vec<AssetData>,
+}
+
+impl Snapshot {
+    pub fn new(
+        config: &Config,
+        strategy: String,
+        block: PinnedBlock,
+        timestamp: u64,
+        weights_sum: U256,
+        fetched: Vec<AssetData>,
+        allocation: Vec<AssetData>,
+    ) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_owned(),
+            config_hash: config.source_hash,
+            voting_contract: config.voting_contract,
+            strategy,
+            block,
+            timestamp,
+            weights_sum,
+            fetched,
+            allocation,
+        }
+    }
+
+    /// File name shared by the JSON and CSV files, sortable by time.
+    pub fn file_stem(&self) -> String {
+        format!("{}-{}", self.timestamp, self.block.number)
+    }
+
+    /// Writes `<stem>.json` and `<stem>.csv` into `dir`, creating it if needed.
+    pub fn write(&self, dir: &Path, config: &Config) -> Result<(PathBuf, PathBuf), Error> {
+        let sink = |path: &Path| {
+            let path = path.display().to_string();
+            move |e: BoxError| Error::sink(path, "Writing the snapshot", e)
+        };
+        fs::create_dir_all(dir).map_err(|e| sink(dir)(e.into()))?;
+        let json_path = dir.join(format!("{}.json", self.file_stem()));
+        let csv_path = dir.join(format!("{}.csv", self.file_stem()));
+
+        let json = fs::File::create(&json_path).map_err(|e| sink(&json_path)(e.into()))?;
+        serde_json::to_writer_pretty(json, self).map_err(|e| sink(&json_path)(e.into()))?;
+        fs::File::create(&csv_path)
+            .and_then(|csv| self.write_csv(csv, config))
+            .map_err(|e| sink(&csv_path)(e.into()))?;
+        Ok((json_path, csv_path))
+    }
+
+    /// One row per fetched asset and reserved slot. The metadata goes in leading `#` lines
+    /// and assets dropped by the filters have an empty `uint16`.
+    pub fn write_csv(&self, mut out: impl Write, config: &Config) -> std::io::Result<()> {
+        writeln!(
+            out,
+            "# jmx-weights snapshot v{}, tool {}, config {}",
+            self.version, self.tool_version, self.config_hash
+        )?;
+        writeln!(
+            out,
+            "# voting_contract {}, block {} {} at {}, timestamp {}, weights_sum {}, strategy {}",
+            self.voting_contract,
+            self.block.number,
+            self.block.hash,
+            self.block.timestamp,
+            self.timestamp,
+            self.weights_sum,
+            self.strategy
+        )?;
+        writeln!(
+            out,
+            "asset_id,symbol,chain,token_address,oft_address,weight,relative_weight,actual_weight,uint16"
+        )?;
+
+        let allocated = |asset: &AssetData| self.allocation.iter().find(|x| x.id == asset.id);
+        let reserved = self
+            .allocation
+            .iter()
+            .filter(|x| !self.fetched.iter().any(|y| y.id == x.id));
+        for asset in self.fetched.iter().chain(reserved) {
+            let allocated = allocated(asset);
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                asset.id,
+                csv_field(asset.symbol.as_deref().unwrap_or("")),
+                csv_field(config.chain_name(&asset.chain_id)),
+                asset.token_addr,
+                asset.oft_address,
+                asset.weight.map(|x| x.to_string()).unwrap_or_default(),
+                asset
+                    .relative_weight
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+                allocated
+                    .and_then(|x| x.actual_weight)
+                    .map(|x| x.to_string())
+                    .unwrap_or_default(),
+                allocated
+                    .and_then(|x| x.converted_weight)
+                    .map(|x| x.to_string())
+                    .unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads a JSON snapshot, refusing other schema versions.
+pub fn read_snapshot(path: &Path) -> Result<Snapshot, Error> {
+    let what = || format!("snapshot {}", path.display());
+    let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let snapshot: Snapshot =
+        serde_json::from_str(&contents).map_err(|e| Error::decode(what(), e))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(Error::decode(
+            what(),
+            format!(
+                "unsupported version {}, expected {}",
+                snapshot.version, SNAPSHOT_VERSION
+            ),
+        ));
+    }
+    Ok(snapshot)
+}
+
+/// Newest JSON snapshot in `dir`, by the unix time its name starts with.
+pub fn latest_snapshot(dir: &Path) -> Result<PathBuf, Error> {
+    let entries = fs::read_dir(dir).map_err(|e| Error::io(dir, e))?;
+    let mut latest: Option<(u64, PathBuf)> = None;
+    for entry in entries {
+        let path = entry.map_err(|e| Error::io(dir, e))?.path();
+        if path.extension().is_none_or(|x| x != "json") {
+            continue;
+        }
+        let Some(timestamp) = path
+            .file_stem()
+            .and_then(|x| x.to_str()?.split('-').next()?.parse::<u64>().ok())
+        else {
+            continue;
+        };
+        if latest.as_ref().is_none_or(|(x, _)| timestamp > *x) {
+            latest = Some((timestamp, path));
+        }
+    }
+    latest.map(|(_, path)| path).ok_or_else(|| {
+        Error::io(
+            dir,
+            std::io::Error::new(std::io::ErrorKind::NotFound, "no snapshot"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::turnover::parse_allocation_csv;
+
+    #[test]
+    fn round_trips_snapshots() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let asset = |i: u8, converted_weight: Option<u16>| AssetData {
+            weight: Some(U256::from(i) * U256::from(10).pow(U256::from(30))),
+            relative_weight: Some(0.5),
+            ..AssetData::test_asset(i, converted_weight)
+        };
+        let snapshot = Snapshot::new(
+            &config,
+            "proportional".to_owned(),
+            PinnedBlock {
+                number: 100,
+                hash: B256::repeat_byte(1),
+                timestamp: 1717200000,
+            },
+            1717200000,
+            U256::from(3) * U256::from(10).pow(U256::from(30)),
+            vec![asset(1, None), asset(2, None)],
+            vec![asset(2, Some(u16::MAX))],
+        );
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let parsed: Snapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.allocation[0].weight, snapshot.allocation[0].weight);
+        assert_eq!(parsed.config_hash, config.source_hash);
+        assert_eq!(parsed.block, snapshot.block);
+
+        // The CSV doubles as a `--previous` allocation
+        let mut csv = Vec::new();
+        snapshot.write_csv(&mut csv, &config).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let previous = parse_allocation_csv(&csv).unwrap();
+        assert_eq!(previous.len(), 1);
+        assert_eq!(previous[0].converted_weight, u16::MAX);
+        assert_eq!(previous[0].oft_address, Some(Address::repeat_byte(2)));
+
+        // A symbol can't shift the columns
+        let mut snapshot = snapshot;
+        snapshot.fetched[0].symbol = Some("A,\"B\"".to_owned());
+        let mut csv = Vec::new();
+        snapshot.write_csv(&mut csv, &config).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains(",\"A,\"\"B\"\"\",BASE,"));
+        snapshot.fetched[1].symbol = Some("B,\nC".to_owned());
+        let mut csv = Vec::new();
+        snapshot.write_csv(&mut csv, &config).unwrap();
+        let previous = parse_allocation_csv(&String::from_utf8(csv).unwrap()).unwrap();
+        assert_eq!(previous[0].symbol, "B,\nC");
+        assert_eq!(previous[0].converted_weight, u16::MAX);
+    }
+}
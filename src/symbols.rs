@@ -0,0 +1,199 @@
+use crate::{AssetData, config::Config};
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// Where the symbol of an asset came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymbolSource {
+    /// Read from the token contract or the Solana metadata account, or set by the config for
+    /// reserved slots
+    #[default]
+    Chain,
+    /// The chain couldn't be read, taken from `[[symbols]]` in the config
+    Override,
+    /// The chain couldn't be read, the symbol it returned last time
+    Cached,
+    /// The chain couldn't be read and no other symbol is known
+    Unknown,
+}
+
+impl SymbolSource {
+    pub fn is_verified(self) -> bool {
+        self == SymbolSource::Chain
+    }
+}
+
+/// Symbol of one asset, as returned by `get_ticker`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticker {
+    pub symbol: Option<String>,
+    pub source: SymbolSource,
+}
+
+impl Ticker {
+    pub fn verified(symbol: String) -> Ticker {
+        Ticker {
+            symbol: Some(symbol),
+            source: SymbolSource::Chain,
+        }
+    }
+}
+
+/// Symbols last read from the chains, by asset id.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SymbolCache(BTreeMap<U256, String>);
+
+impl SymbolCache {
+    /// Reads the cache at `path`. A missing or unreadable cache only loses the fallback, so it
+    /// is reported and treated as empty.
+    pub fn load(path: &Path) -> SymbolCache {
+        let contents = match fs::read_to_string(path) {
+            Ok(val) => val,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return SymbolCache::default(),
+            Err(e) => {
+                eprintln!("Can't read symbol cache {}: {}", path.display(), e);
+                return SymbolCache::default();
+            }
+        };
+        serde_json::from_str(&contents).unwrap_or_else(|e| {
+            eprintln!("Ignoring malformed symbol cache {}: {}", path.display(), e);
+            SymbolCache::default()
+        })
+    }
+
+    /// Writes the cache to `path`, failures are reported and otherwise ignored.
+    pub fn save(&self, path: &Path) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(std::io::Error::from)
+            .and_then(|json| fs::write(path, json));
+        if let Err(e) = result {
+            eprintln!("Can't write symbol cache {}: {}", path.display(), e);
+        }
+    }
+
+    pub fn get(&self, id: &U256) -> Option<&str> {
+        self.0.get(id).map(String::as_str)
+    }
+
+    /// Records the verified symbols, returns whether anything changed.
+    pub fn update(&mut self, asset_data: &[AssetData], tickers: &[Ticker]) -> bool {
+        let mut changed = false;
+        for (asset, ticker) in asset_data.iter().zip(tickers) {
+            if let (SymbolSource::Chain, Some(symbol)) = (ticker.source, &ticker.symbol)
+                && self.0.get(&asset.id) != Some(symbol)
+            {
+                self.0.insert(asset.id, symbol.clone());
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Symbol of an asset whose chain couldn't be read: the configured override, else the
+/// cached one.
+pub fn fallback_ticker(asset: &AssetData, config: &Config, cache: &SymbolCache) -> Ticker {
+    if let Some(symbol) = config.symbol_overrides.get(&asset.token_addr) {
+        return Ticker {
+            symbol: Some(symbol.clone()),
+            source: SymbolSource::Override,
+        };
+    }
+    match cache.get(&asset.id) {
+        Some(symbol) => Ticker {
+            symbol: Some(symbol.to_owned()),
+            source: SymbolSource::Cached,
+        },
+        None => Ticker {
+            symbol: None,
+            source: SymbolSource::Unknown,
+        },
+    }
+}
+
+/// Lists the symbols that weren't read from the chain on stderr, stdout may carry a CSV.
+pub fn print_unverified(asset_data: &[AssetData], tickers: &[Ticker], config: &Config) {
+    let unverified: Vec<_> = asset_data
+        .iter()
+        .zip(tickers)
+        .filter(|(_, ticker)| !ticker.source.is_verified())
+        .collect();
+    if unverified.is_empty() {
+        return;
+    }
+    eprintln!("Unverified symbols:");
+    for (asset, ticker) in unverified {
+        let source = match ticker.source {
+            SymbolSource::Override => "config override",
+            SymbolSource::Cached => "cached",
+            _ => "unknown",
+        };
+        eprintln!(
+            "  {:<12} {:<16} {} on {}",
+            ticker.symbol.as_deref().unwrap_or("-"),
+            source,
+            asset.token_addr,
+            config.chain_name(&asset.chain_id)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch_data::decode_asset_ids;
+    use alloy::primitives::Address;
+
+    #[test]
+    fn falls_back_to_override_then_cache() {
+        let mut config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let ids = [
+            U256::from(1) << 160 | U256::from(1),
+            U256::from(1) << 160 | U256::from(2),
+        ];
+        let assets = decode_asset_ids(&ids, &config);
+        config
+            .symbol_overrides
+            .insert(Address::with_last_byte(1), "ONE".to_owned());
+
+        let mut cache = SymbolCache::default();
+        assert!(cache.update(
+            &assets,
+            &[
+                Ticker::verified("UNO".to_owned()),
+                Ticker::verified("TWO".to_owned())
+            ]
+        ));
+        assert!(!cache.update(
+            &assets,
+            &[
+                Ticker::verified("UNO".to_owned()),
+                fallback_ticker(&assets[1], &config, &cache)
+            ]
+        ));
+
+        assert_eq!(
+            fallback_ticker(&assets[0], &config, &cache),
+            Ticker {
+                symbol: Some("ONE".to_owned()),
+                source: SymbolSource::Override
+            }
+        );
+        assert_eq!(
+            fallback_ticker(&assets[1], &config, &cache).source,
+            SymbolSource::Cached
+        );
+        assert_eq!(
+            fallback_ticker(&assets[1], &config, &SymbolCache::default()),
+            Ticker {
+                symbol: None,
+                source: SymbolSource::Unknown
+            }
+        );
+
+        let json = serde_json::to_string(&cache).unwrap();
+        assert_eq!(serde_json::from_str::<SymbolCache>(&json).unwrap(), cache);
+    }
+}
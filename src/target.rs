@@ -0,0 +1,432 @@
+use crate::{
+    AssetData,
+    config::{Target, WeightOrder},
+    error::Error,
+};
+use alloy::{
+    dyn_abi::{DynSolValue, FunctionExt, JsonAbiExt},
+    json_abi::{Function, JsonAbi},
+    primitives::{Address, Bytes, U256},
+};
+use std::{collections::HashSet, fs};
+
+/// How the weights are laid out in the arguments of the target method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightsLayout {
+    /// Parallel `address[]` and `uint16[]` inputs, the address one at this position
+    Arrays { addresses: usize },
+    /// A single `(address,uint16)[]` input, the address at this position of the tuple
+    Tuples { address: usize },
+}
+
+/// Function of the target contract that sets the weights.
+#[derive(Debug, Clone)]
+pub struct WeightsMethod {
+    pub function: Function,
+    pub layout: WeightsLayout,
+}
+
+impl WeightsMethod {
+    pub fn load(target: &Target) -> Result<WeightsMethod, Error> {
+        WeightsMethod::from_abi(&load_abi(target)?, &target.method).map_err(|e| in_abi(target, e))
+    }
+
+    pub fn from_abi(abi: &JsonAbi, method: &str) -> Result<WeightsMethod, Error> {
+        let function = find_function(abi, method)?;
+        let types: Vec<String> = function
+            .inputs
+            .iter()
+            .map(|x| x.selector_type().into_owned())
+            .collect();
+        let types: Vec<&str> = types.iter().map(String::as_str).collect();
+        let layout = match types.as_slice() {
+            ["address[]", "uint16[]"] => WeightsLayout::Arrays { addresses: 0 },
+            ["uint16[]", "address[]"] => WeightsLayout::Arrays { addresses: 1 },
+            ["(address,uint16)[]"] => WeightsLayout::Tuples { address: 0 },
+            ["(uint16,address)[]"] => WeightsLayout::Tuples { address: 1 },
+            _ => {
+                return Err(Error::config(format!(
+                    "`{}` must take an address[] and a uint16[], or an (address,uint16)[]",
+                    function.signature()
+                )));
+            }
+        };
+        Ok(WeightsMethod {
+            function: function.clone(),
+            layout,
+        })
+    }
+
+    /// Name of each input, its position when the ABI leaves it unnamed.
+    pub fn input_names(&self) -> Vec<String> {
+        self.function
+            .inputs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| match x.name.is_empty() {
+                true => i.to_string(),
+                false => x.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Arguments of the call, in input order.
+    pub fn arguments(&self, weights: &[(Address, u16)]) -> Vec<DynSolValue> {
+        let address = |x: &Address| DynSolValue::Address(*x);
+        let weight = |x: &u16| DynSolValue::Uint(U256::from(*x), 16);
+        match self.layout {
+            WeightsLayout::Arrays { addresses } => {
+                let mut arguments = vec![
+                    DynSolValue::Array(weights.iter().map(|(x, _)| address(x)).collect()),
+                    DynSolValue::Array(weights.iter().map(|(_, x)| weight(x)).collect()),
+                ];
+                if addresses == 1 {
+                    arguments.swap(0, 1);
+                }
+                arguments
+            }
+            WeightsLayout::Tuples { address: position } => {
+                vec![DynSolValue::Array(
+                    weights
+                        .iter()
+                        .map(|(x, y)| {
+                            let mut fields = vec![address(x), weight(y)];
+                            if position == 1 {
+                                fields.swap(0, 1);
+                            }
+                            DynSolValue::Tuple(fields)
+                        })
+                        .collect(),
+                )]
+            }
+        }
+    }
+
+    /// Selector followed by the ABI encoded arguments.
+    pub fn calldata(&self, weights: &[(Address, u16)]) -> Result<Bytes, Error> {
+        Ok(self
+            .function
+            .abi_encode_input(&self.arguments(weights))
+            .map_err(|e| Error::decode(format!("arguments of {}", self.function.signature()), e))?
+            .into())
+    }
+
+    /// Decodes calldata of this method back into its arguments, for review.
+    pub fn decode(&self, calldata: &[u8]) -> Result<Vec<DynSolValue>, Error> {
+        let what = || format!("calldata of {}", self.function.signature());
+        let selector = self.function.selector();
+        if calldata.get(..4) != Some(selector.as_slice()) {
+            return Err(Error::decode(
+                what(),
+                format!("doesn't start with the selector {}", selector),
+            ));
+        }
+        self.function
+            .abi_decode_input(&calldata[4..])
+            .map_err(|e| Error::decode(what(), e))
+    }
+}
+
+/// View function of the target contract returning the weight of one OFT address.
+#[derive(Debug, Clone)]
+pub struct ReadMethod {
+    pub function: Function,
+}
+
+impl ReadMethod {
+    pub fn load(target: &Target) -> Result<ReadMethod, Error> {
+        let method = target.read_method.as_deref().ok_or_else(|| {
+            Error::config("No read_method for the [target] contract in the config")
+        })?;
+        ReadMethod::from_abi(&load_abi(target)?, method).map_err(|e| in_abi(target, e))
+    }
+
+    pub fn from_abi(abi: &JsonAbi, method: &str) -> Result<ReadMethod, Error> {
+        let function = find_function(abi, method)?;
+        let inputs: Vec<&str> = function.inputs.iter().map(|x| x.ty.as_str()).collect();
+        let outputs: Vec<&str> = function.outputs.iter().map(|x| x.ty.as_str()).collect();
+        match (inputs.as_slice(), outputs.as_slice()) {
+            (["address"], [output]) if output.starts_with("uint") => Ok(ReadMethod {
+                function: function.clone(),
+            }),
+            _ => Err(Error::config(format!(
+                "`{}` must take an address and return a single uint",
+                function.signature_with_outputs()
+            ))),
+        }
+    }
+
+    pub fn calldata(&self, address: Address) -> Result<Bytes, Error> {
+        Ok(self
+            .function
+            .abi_encode_input(&[DynSolValue::Address(address)])
+            .map_err(|e| Error::decode(format!("arguments of {}", self.function.signature()), e))?
+            .into())
+    }
+
+    /// Decodes the returned weight, which has to fit in a u16.
+    pub fn decode_weight(&self, data: &[u8]) -> Result<u16, Error> {
+        let what = || format!("output of {}", self.function.signature());
+        match self
+            .function
+            .abi_decode_output(data)
+            .map_err(|e| Error::decode(what(), e))?
+            .as_slice()
+        {
+            [DynSolValue::Uint(weight, _)] => u16::try_from(*weight)
+                .map_err(|_| Error::decode(what(), format!("{} doesn't fit in a u16", weight))),
+            _ => Err(Error::decode(what(), "not a single uint")),
+        }
+    }
+}
+
+/// View function of the target contract listing every address it holds a weight for.
+#[derive(Debug, Clone)]
+pub struct ListMethod {
+    pub function: Function,
+}
+
+impl ListMethod {
+    /// `None` when the config has no `list_method`.
+    pub fn load(target: &Target) -> Result<Option<ListMethod>, Error> {
+        let Some(method) = target.list_method.as_deref() else {
+            return Ok(None);
+        };
+        ListMethod::from_abi(&load_abi(target)?, method)
+            .map(Some)
+            .map_err(|e| in_abi(target, e))
+    }
+
+    pub fn from_abi(abi: &JsonAbi, method: &str) -> Result<ListMethod, Error> {
+        let function = find_function(abi, method)?;
+        let outputs: Vec<&str> = function.outputs.iter().map(|x| x.ty.as_str()).collect();
+        match (function.inputs.is_empty(), outputs.as_slice()) {
+            (true, ["address[]"]) => Ok(ListMethod {
+                function: function.clone(),
+            }),
+            _ => Err(Error::config(format!(
+                "`{}` must take no argument and return an address[]",
+                function.signature_with_outputs()
+            ))),
+        }
+    }
+
+    pub fn calldata(&self) -> Bytes {
+        self.function.selector().to_vec().into()
+    }
+
+    pub fn decode_addresses(&self, data: &[u8]) -> Result<Vec<Address>, Error> {
+        let what = || format!("output of {}", self.function.signature());
+        match self
+            .function
+            .abi_decode_output(data)
+            .map_err(|e| Error::decode(what(), e))?
+            .as_slice()
+        {
+            [DynSolValue::Array(addresses)] => addresses
+                .iter()
+                .map(|x| {
+                    x.as_address()
+                        .ok_or_else(|| Error::decode(what(), "not an address"))
+                })
+                .collect(),
+            _ => Err(Error::decode(what(), "not an address[]")),
+        }
+    }
+}
+
+fn load_abi(target: &Target) -> Result<JsonAbi, Error> {
+    let contents = fs::read_to_string(&target.abi).map_err(|e| Error::io(&target.abi, e))?;
+    serde_json::from_str(&contents)
+        .map_err(|e| Error::decode(format!("ABI {}", target.abi.display()), e))
+}
+
+/// Points a method lookup error at the ABI file it came from.
+fn in_abi(target: &Target, source: Error) -> Error {
+    Error::Config {
+        message: format!("In ABI {}", target.abi.display()),
+        source: Some(source.into()),
+    }
+}
+
+/// Looks `method` up in `abi`, either by name or, for overloaded functions, by its full
+/// signature, e.g. `setWeights((address,uint16)[])`.
+fn find_function<'a>(abi: &'a JsonAbi, method: &str) -> Result<&'a Function, Error> {
+    let (name, signature) = match method.split_once('(') {
+        Some((name, _)) => (name, Some(method.replace(' ', ""))),
+        None => (method, None),
+    };
+    let functions = abi
+        .function(name)
+        .ok_or_else(|| Error::config(format!("No function `{}`", name)))?;
+    match signature {
+        Some(signature) => functions
+            .iter()
+            .find(|x| x.signature() == signature)
+            .ok_or_else(|| {
+                Error::config(format!(
+                    "No function `{}`, the ABI has {}",
+                    signature,
+                    functions
+                        .iter()
+                        .map(|x| x.signature())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }),
+        None => match functions.as_slice() {
+            [function] => Ok(function),
+            _ => Err(Error::config(format!(
+                "Function `{}` is overloaded, give its full signature as the method",
+                name
+            ))),
+        },
+    }
+}
+
+/// Writes a value the way `cast` takes it on the command line: arrays as `[a,b]` and tuples
+/// as `(a,b)`.
+pub fn format_cast_value(value: &DynSolValue) -> String {
+    format_value(value, ("(", ")"))
+}
+
+/// Writes `value` with the given tuple delimiters, arrays in square brackets.
+pub fn format_value(value: &DynSolValue, tuple: (&str, &str)) -> String {
+    let join = |values: &[DynSolValue]| {
+        values
+            .iter()
+            .map(|x| format_value(x, tuple))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    match value {
+        DynSolValue::Address(x) => x.to_checksum(None),
+        DynSolValue::Uint(x, _) => x.to_string(),
+        DynSolValue::Array(x) | DynSolValue::FixedArray(x) => format!("[{}]", join(x)),
+        DynSolValue::Tuple(x) => format!("{}{}{}", tuple.0, join(x), tuple.1),
+        x => format!("{:?}", x),
+    }
+}
+
+/// Prints the calldata setting `weights` on the target, its decoded arguments and the
+/// `cast` commands that rebuild and send it.
+pub fn print_calldata(
+    target: &Target,
+    method: &WeightsMethod,
+    weights: &[(Address, u16)],
+) -> Result<(), Error> {
+    let calldata = method.calldata(weights)?;
+    let signature = method.function.signature();
+    println!(
+        "{} on {} (chain {}), selector {}",
+        signature,
+        target.address,
+        target.chain_id,
+        method.function.selector()
+    );
+    println!("{}", calldata);
+
+    // Decoded from the calldata itself, not from the allocation
+    let arguments = method.decode(&calldata)?;
+    let names = method.input_names();
+    for ((name, input), argument) in names
+        .iter()
+        .zip(method.function.inputs.iter())
+        .zip(arguments.iter())
+    {
+        println!("  {} {}:", input.selector_type(), name);
+        match argument.as_array() {
+            Some(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    println!("    [{}] {}", i, format_cast_value(value));
+                }
+            }
+            None => println!("    {}", format_cast_value(argument)),
+        }
+    }
+
+    let arguments: Vec<String> = arguments
+        .iter()
+        .map(|x| format!("\"{}\"", format_cast_value(x)))
+        .collect();
+    println!("cast calldata \"{}\" {}", signature, arguments.join(" "));
+    println!(
+        "cast send {} \"{}\" {} --rpc-url <RPC>",
+        target.address,
+        signature,
+        arguments.join(" ")
+    );
+    Ok(())
+}
+
+/// OFT address and u16 weight of every allocated asset, reserved slots included, in the
+/// order the target contract expects.
+pub fn target_weights(
+    allocation: &[AssetData],
+    order: WeightOrder,
+) -> Result<Vec<(Address, u16)>, Error> {
+    let mut seen = HashSet::new();
+    let mut weights = Vec::new();
+    for asset in allocation.iter() {
+        if !seen.insert(asset.oft_address) {
+            return Err(Error::Allocation(format!(
+                "{} is allocated twice under OFT {}, the target is keyed by OFT address",
+                asset.label(),
+                asset.oft_address
+            )));
+        }
+        weights.push((asset.oft_address, asset.allocated_weight()?));
+    }
+    match order {
+        WeightOrder::Address => weights.sort(),
+        WeightOrder::Weight => {
+            weights.sort_by_key(|(address, weight)| (u16::MAX - weight, *address))
+        }
+    }
+    Ok(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy::{sol, sol_types::SolCall};
+
+    sol!(ITarget, "tests/fixtures/target_abi.json");
+
+    #[test]
+    fn encodes_like_the_sol_bindings() {
+        let abi: JsonAbi =
+            serde_json::from_str(include_str!("../tests/fixtures/target_abi.json")).unwrap();
+        let method = WeightsMethod::from_abi(&abi, "setWeights(address[], uint16[])").unwrap();
+        let weights = vec![
+            (Address::repeat_byte(1), 30000),
+            (Address::repeat_byte(2), 35535),
+        ];
+        let calldata = method.calldata(&weights).unwrap();
+        let expected = ITarget::setWeightsCall {
+            assets: weights.iter().map(|(x, _)| *x).collect(),
+            weights: weights.iter().map(|(_, x)| *x).collect(),
+        }
+        .abi_encode();
+        assert_eq!(calldata.as_ref(), expected.as_slice());
+        assert_eq!(
+            method.decode(&calldata).unwrap(),
+            method.arguments(&weights)
+        );
+
+        let tuples =
+            JsonAbi::parse(["function setAllocation((uint16,address)[] weights)"]).unwrap();
+        let method = WeightsMethod::from_abi(&tuples, "setAllocation").unwrap();
+        assert_eq!(method.layout, WeightsLayout::Tuples { address: 1 });
+        assert_eq!(
+            format_cast_value(&method.arguments(&weights[..1])[0]),
+            format!("[(30000,{})]", Address::repeat_byte(1))
+        );
+
+        let list = ListMethod::from_abi(&abi, "assets").unwrap();
+        let addresses = vec![Address::repeat_byte(1), Address::repeat_byte(2)];
+        let returned = ITarget::assetsCall::abi_encode_returns(&addresses);
+        assert_eq!(list.decode_addresses(&returned).unwrap(), addresses);
+        assert!(ListMethod::from_abi(&abi, "weights").is_err());
+    }
+}
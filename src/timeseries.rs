@@ -0,0 +1,256 @@
+use crate::{
+    AssetData, VotingContract,
+    allocation::AllocationStrategy,
+    config::Config,
+    csv_field,
+    error::Error,
+    fetch_data::{block_at, decode_asset_ids, get_asset_ids, get_points_at, get_ticker, pin_block},
+    finalize_allocation, u256_division,
+    variables::WEEK,
+};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, U256},
+    providers::DynProvider,
+};
+use chrono::DateTime;
+use futures::StreamExt;
+use op_alloy_network::Optimism;
+use std::{collections::HashMap, io::Write};
+
+/// Weeks fetched at the same time, each one is a couple of multicalls.
+const CONCURRENT_WEEKS: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct TimeseriesRow {
+    pub timestamp: u64,
+    pub asset_id: U256,
+    pub symbol: String,
+    pub chain: String,
+    pub token_addr: Address,
+    pub points: U256,
+    pub relative_weight: f64,
+    pub actual_weight: Option<f64>,
+    pub converted_weight: Option<u16>,
+}
+
+/// Walks the weeks from `from` to `to` and rebuilds the allocation of each one from
+/// `pointsOfAt`/`pointsSum`. The relative weight is the one the allocation was built from.
+/// Each week covers the assets listed at the last block before it starts.
+pub async fn build_timeseries(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    config: &Config,
+    strategy: &dyn AllocationStrategy,
+    from: u64,
+    to: u64,
+    step_weeks: u64,
+) -> Result<Vec<TimeseriesRow>, Error> {
+    // Every week lists the assets at its own block, so delisted assets keep their history
+    let latest = pin_block(provider, None).await?;
+    let weeks = week_starts(from, to, step_weeks);
+    let listed: Vec<Result<Vec<U256>, Error>> = futures::stream::iter(weeks.iter().copied())
+        .map(|week| async move {
+            let block = block_at(provider, latest, week).await?;
+            get_asset_ids(contract, block.block_id()).await
+        })
+        .buffered(CONCURRENT_WEEKS)
+        .collect()
+        .await;
+    let listed = listed.into_iter().collect::<Result<Vec<_>, Error>>()?;
+
+    // Tickers are looked up once for every asset seen in any week
+    let mut asset_ids: Vec<U256> = Vec::new();
+    for id in listed.iter().flatten() {
+        if !asset_ids.contains(id) {
+            asset_ids.push(*id);
+        }
+    }
+    let mut asset_data = decode_asset_ids(&asset_ids, config);
+    let tickers = get_ticker(&asset_data, config).await;
+    for (asset, ticker) in asset_data.iter_mut().zip(tickers) {
+        asset.set_ticker(ticker);
+    }
+    let by_id: HashMap<U256, AssetData> = asset_data.into_iter().map(|x| (x.id, x)).collect();
+
+    let by_id = &by_id;
+    let rows: Vec<Result<Vec<TimeseriesRow>, Error>> =
+        futures::stream::iter(weeks.into_iter().zip(listed))
+            .map(|(week, ids)| async move {
+                let asset_data: Vec<AssetData> = ids.iter().map(|id| by_id[id].clone()).collect();
+                let (points, points_sum) = get_points_at(
+                    provider.clone(),
+                    contract,
+                    &asset_data,
+                    week,
+                    BlockId::latest(),
+                )
+                .await?;
+                week_rows(&asset_data, config, strategy, week, &points, points_sum)
+            })
+            .buffered(CONCURRENT_WEEKS)
+            .collect()
+            .await;
+
+    let mut out = Vec::new();
+    for week in rows {
+        out.extend(week?);
+    }
+    Ok(out)
+}
+
+/// Start of every `step_weeks`-th week from the one containing `from` up to `to`.
+pub fn week_starts(from: u64, to: u64, step_weeks: u64) -> Vec<u64> {
+    (from / WEEK * WEEK..=to)
+        .step_by((WEEK * step_weeks.max(1)) as usize)
+        .collect()
+}
+
+fn week_rows(
+    asset_data: &[AssetData],
+    config: &Config,
+    strategy: &dyn AllocationStrategy,
+    week: u64,
+    points: &[U256],
+    points_sum: U256,
+) -> Result<Vec<TimeseriesRow>, Error> {
+    if points_sum.is_zero() {
+        eprintln!("No votes in the week of {}, skipping", week);
+        return Ok(Vec::new());
+    }
+
+    let mut weighted = asset_data.to_vec();
+    for (asset, points) in weighted.iter_mut().zip(points) {
+        asset.weight = Some(*points);
+        asset.relative_weight = u256_division(points, &points_sum);
+    }
+    let mut allocation: HashMap<U256, AssetData> = finalize_allocation(weighted, config, strategy)?
+        .into_iter()
+        .map(|x| (x.id, x))
+        .collect();
+
+    let mut rows: Vec<TimeseriesRow> = asset_data
+        .iter()
+        .zip(points)
+        .map(|(asset, points)| {
+            let allocated = allocation.remove(&asset.id);
+            TimeseriesRow {
+                timestamp: week,
+                asset_id: asset.id,
+                symbol: asset.symbol.clone().unwrap_or_default(),
+                chain: config.chain_name(&asset.chain_id).to_owned(),
+                token_addr: asset.token_addr,
+                points: *points,
+                relative_weight: u256_division(points, &points_sum).unwrap_or_default(),
+                actual_weight: allocated.as_ref().and_then(|x| x.actual_weight),
+                converted_weight: allocated.as_ref().and_then(|x| x.converted_weight),
+            }
+        })
+        .collect();
+
+    // Whatever is left was not voted on, i.e. the reserved slots
+    rows.extend(allocation.into_values().map(|asset| TimeseriesRow {
+        timestamp: week,
+        asset_id: asset.id,
+        symbol: asset.symbol.unwrap_or_default(),
+        chain: config.chain_name(&asset.chain_id).to_owned(),
+        token_addr: asset.token_addr,
+        points: U256::ZERO,
+        relative_weight: 0.,
+        actual_weight: asset.actual_weight,
+        converted_weight: asset.converted_weight,
+    }));
+    Ok(rows)
+}
+
+pub fn write_timeseries_csv(rows: &[TimeseriesRow], mut out: impl Write) -> std::io::Result<()> {
+    writeln!(
+        out,
+        "timestamp,date,asset_id,symbol,chain,token_address,points,relative_weight,actual_weight,uint16"
+    )?;
+    for row in rows.iter() {
+        let date = DateTime::from_timestamp(row.timestamp as i64, 0)
+            .map(|x| x.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        writeln!(
+            out,
+            "{},{},{},{},{},{},{},{},{},{}",
+            row.timestamp,
+            date,
+            row.asset_id,
+            csv_field(&row.symbol),
+            csv_field(&row.chain),
+            row.token_addr,
+            row.points,
+            row.relative_weight,
+            row.actual_weight.map(|x| x.to_string()).unwrap_or_default(),
+            row.converted_weight
+                .map(|x| x.to_string())
+                .unwrap_or_default()
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::Proportional;
+
+    #[test]
+    fn walks_the_weeks() {
+        // 2024-06-06 is a Thursday, weeks start on Thursdays at 00:00 UTC
+        let start = 1717632000;
+        assert_eq!(start % WEEK, 0);
+        assert_eq!(
+            week_starts(start + 1000, start + 3 * WEEK, 1),
+            vec![start, start + WEEK, start + 2 * WEEK, start + 3 * WEEK]
+        );
+        assert_eq!(
+            week_starts(start, start + 3 * WEEK - 1, 2),
+            vec![start, start + 2 * WEEK]
+        );
+        assert_eq!(week_starts(start + WEEK, start, 1), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn writes_rows_from_the_points() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let assets = vec![
+            AssetData {
+                symbol: Some("A,B".to_owned()),
+                ..AssetData::test_asset(1, None)
+            },
+            AssetData {
+                symbol: Some("say \"hi\"".to_owned()),
+                ..AssetData::test_asset(2, None)
+            },
+        ];
+        let points = [U256::from(3), U256::from(1)];
+        let rows = week_rows(&assets, &config, &Proportional, 0, &points, U256::from(4)).unwrap();
+
+        // Both assets and the reserved slot, the relative weight matching the allocation
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].relative_weight, 0.75);
+        assert_eq!(rows[1].relative_weight, 0.25);
+        let total: u32 = rows
+            .iter()
+            .filter_map(|x| x.converted_weight)
+            .map(u32::from)
+            .sum();
+        assert_eq!(total, u16::MAX as u32);
+        assert!(
+            week_rows(&assets, &config, &Proportional, 0, &points, U256::ZERO)
+                .unwrap()
+                .is_empty()
+        );
+
+        let mut out = Vec::new();
+        write_timeseries_csv(&rows[..2], &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with(&format!("0,1970-01-01,{},\"A,B\",BASE,", assets[0].id)));
+        assert!(lines[2].contains(",\"say \"\"hi\"\"\",BASE,"));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::{
+    AssetData, allocation::capped_largest_remainder, config::Config, error::Error,
+    read_data::read_sheet_history, snapshot::read_snapshot, write_data::get_sheets_hub,
+};
+use alloy::primitives::{Address, U256};
+use std::{fs, path::PathBuf, str::FromStr};
+
+/// Where the allocation of the last rebalance is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreviousSource {
+    /// The newest tab of the Google Sheet
+    Sheet,
+    /// A JSON snapshot, or a CSV file with `oft_address` and `uint16` columns
+    File(PathBuf),
+}
+
+impl FromStr for PreviousSource {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "sheet" => Ok(PreviousSource::Sheet),
+            "" => Err("expected `sheet` or a file path".to_owned()),
+            path => Ok(PreviousSource::File(PathBuf::from(path))),
+        }
+    }
+}
+
+/// One asset of the previous allocation. Older sheet tabs only have the symbol and chain
+/// name, newer ones and files also have the OFT address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviousEntry {
+    pub symbol: String,
+    pub chain: String,
+    pub oft_address: Option<Address>,
+    pub converted_weight: u16,
+}
+
+impl PreviousEntry {
+    pub fn matches(&self, asset: &AssetData, config: &Config) -> bool {
+        match self.oft_address {
+            Some(oft_address) => oft_address == asset.oft_address,
+            None => {
+                Some(self.symbol.as_str()) == asset.symbol.as_deref()
+                    && self.chain == config.chain_name(&asset.chain_id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TurnoverReport {
+    /// Assets that kept their previous u16 weight
+    pub kept: Vec<AssetData>,
+    /// Share of the index that would have traded with the fresh allocation
+    pub raw_turnover: f64,
+    /// Share of the index that trades with the damped allocation
+    pub turnover: f64,
+}
+
+/// Loads the previous allocation from the newest sheet tab, a JSON snapshot or a CSV file.
+pub async fn load_previous(source: &PreviousSource) -> Result<Vec<PreviousEntry>, Error> {
+    match source {
+        PreviousSource::Sheet => {
+            let hub = get_sheets_hub().await?;
+            let snapshot = read_sheet_history(&hub, 1)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::decode("the spreadsheet", "it has no tabs"))?;
+            Ok(snapshot
+                .rows
+                .into_iter()
+                .map(|row| PreviousEntry {
+                    symbol: row.symbol,
+                    chain: row.chain,
+                    oft_address: row.oft_address,
+                    converted_weight: row.converted_weight,
+                })
+                .collect())
+        }
+        PreviousSource::File(path) if path.extension().is_some_and(|x| x == "json") => {
+            let snapshot = read_snapshot(path)?;
+            Ok(snapshot
+                .allocation
+                .into_iter()
+                .map(|asset| PreviousEntry {
+                    symbol: asset.symbol.unwrap_or_default(),
+                    chain: String::new(),
+                    oft_address: Some(asset.oft_address),
+                    converted_weight: asset.converted_weight.unwrap_or_default(),
+                })
+                .collect())
+        }
+        PreviousSource::File(path) => {
+            let contents = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+            parse_allocation_csv(&contents).map_err(|e| match e {
+                Error::Decode { reason, .. } => {
+                    Error::decode(format!("previous allocation {}", path.display()), reason)
+                }
+                e => e,
+            })
+        }
+    }
+}
+
+/// Reads a CSV with a header naming at least the `oft_address` and `uint16` columns,
+/// `symbol` and `chain` are used when present. Fields may be quoted. `#` lines and rows
+/// without a `uint16` are skipped, so snapshot CSVs can be read as is.
+pub fn parse_allocation_csv(contents: &str) -> Result<Vec<PreviousEntry>, Error> {
+    let invalid = |reason: String| Error::decode("allocation CSV", reason);
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(contents.as_bytes());
+    let header = reader
+        .headers()
+        .map_err(|e| invalid(e.to_string()))?
+        .clone();
+    if header.is_empty() {
+        return Err(invalid("empty file".to_owned()));
+    }
+    let column = |name: &str| header.iter().position(|x| x == name);
+    let oft_column =
+        column("oft_address").ok_or_else(|| invalid("no oft_address column".to_owned()))?;
+    let weight_column = column("uint16").ok_or_else(|| invalid("no uint16 column".to_owned()))?;
+    let (symbol_column, chain_column) = (column("symbol"), column("chain"));
+
+    let mut out = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(|e| invalid(e.to_string()))?;
+        let line = record.position().map_or(0, |x| x.line());
+        let field = |column: Option<usize>| column.and_then(|x| record.get(x)).unwrap_or_default();
+        // Assets a snapshot fetched but did not allocate
+        if field(Some(weight_column)).is_empty() {
+            continue;
+        }
+        out.push(PreviousEntry {
+            symbol: field(symbol_column).to_owned(),
+            chain: field(chain_column).to_owned(),
+            oft_address: Some(
+                field(Some(oft_column))
+                    .parse()
+                    .map_err(|_| invalid(format!("line {}: invalid oft_address", line)))?,
+            ),
+            converted_weight: field(Some(weight_column))
+                .parse()
+                .map_err(|_| invalid(format!("line {}: invalid uint16", line)))?,
+        });
+    }
+    Ok(out)
+}
+
+/// Keeps the previous u16 weight of every voted asset that moved by less than `min_change`
+/// units and apportions the rest of the voting pool over the assets that moved, in
+/// proportion to their new weight. Reserved slots are never touched. When every asset moved
+/// by less than `min_change` the previous allocation is kept whole, provided it still fills
+/// the voting pool. The fresh allocation is only used as is when the kept weights can't be
+/// made to sum to the pool.
+pub fn apply_min_change(
+    allocation: &mut [AssetData],
+    previous: &[PreviousEntry],
+    config: &Config,
+    min_change: u16,
+) -> Result<TurnoverReport, Error> {
+    let raw_turnover = turnover(allocation, previous, config);
+    let previous_weight = |asset: &AssetData| {
+        previous
+            .iter()
+            .find(|x| x.matches(asset, config))
+            .map(|x| x.converted_weight)
+    };
+
+    let mut kept = Vec::new();
+    let mut moving = Vec::new();
+    for (i, asset) in allocation.iter().enumerate() {
+        if config.is_reserved(&asset.token_addr, &asset.chain_id) {
+            continue;
+        }
+        let new = asset.allocated_weight()?;
+        match previous_weight(asset) {
+            Some(old) if new.abs_diff(old) < min_change => kept.push((i, old)),
+            _ => moving.push((i, new)),
+        }
+    }
+
+    let kept_sum: u32 = kept.iter().map(|(_, old)| *old as u32).sum();
+    let pool = (u16::MAX - config.reserved_weight()) as u32;
+    let damped = match pool.checked_sub(kept_sum) {
+        // Only noise since the last rebalance
+        Some(0) if moving.is_empty() => Some(Vec::new()),
+        Some(left) if !moving.is_empty() => {
+            let weights: Vec<(U256, U256)> = moving
+                .iter()
+                .map(|(i, new)| (allocation[*i].id, U256::from(*new)))
+                .collect();
+            let caps: Vec<u16> = moving
+                .iter()
+                .map(|(i, _)| config.weight_cap(&allocation[*i].token_addr))
+                .collect();
+            capped_largest_remainder(&weights, &caps, left as u16)
+        }
+        _ => None,
+    };
+    let Some(damped) = damped else {
+        return Ok(TurnoverReport {
+            kept: Vec::new(),
+            raw_turnover,
+            turnover: raw_turnover,
+        });
+    };
+
+    let kept_assets = kept
+        .iter()
+        .filter(|(i, old)| allocation[*i].converted_weight != Some(*old))
+        .map(|(i, _)| allocation[*i].clone())
+        .collect();
+    for (i, old) in kept {
+        set_weight(&mut allocation[i], old);
+    }
+    for ((i, _), new) in moving.into_iter().zip(damped) {
+        set_weight(&mut allocation[i], new);
+    }
+    Ok(TurnoverReport {
+        kept: kept_assets,
+        raw_turnover,
+        turnover: turnover(allocation, previous, config),
+    })
+}
+
+fn set_weight(asset: &mut AssetData, converted_weight: u16) {
+    asset.converted_weight = Some(converted_weight);
+    asset.actual_weight = Some(converted_weight as f64 / u16::MAX as f64);
+}
+
+/// Share of the index that changes hands going from `previous` to `allocation`, half the
+/// sum of the absolute changes so a swap between two assets counts once.
+pub fn turnover(allocation: &[AssetData], previous: &[PreviousEntry], config: &Config) -> f64 {
+    let mut moved: u64 = 0;
+    let mut matched = vec![false; previous.len()];
+    for asset in allocation.iter() {
+        let new = asset.converted_weight.unwrap_or_default();
+        match previous.iter().position(|x| x.matches(asset, config)) {
+            Some(i) => {
+                matched[i] = true;
+                moved += new.abs_diff(previous[i].converted_weight) as u64;
+            }
+            None => moved += new as u64,
+        }
+    }
+    // Dropped since the last rebalance
+    moved += previous
+        .iter()
+        .zip(matched)
+        .filter(|(_, matched)| !matched)
+        .map(|(x, _)| x.converted_weight as u64)
+        .sum::<u64>();
+    moved as f64 / 2. / u16::MAX as f64
+}
+
+pub fn print_turnover_report(report: &TurnoverReport, min_change: f64) {
+    for asset in report.kept.iter() {
+        println!(
+            "Kept {} at its previous weight, moved by less than {:.2}%",
+            asset.report_symbol(),
+            min_change * 100.
+        );
+    }
+    println!(
+        "Turnover {:.4}% of the index, {:.4}% without the minimum change",
+        report.turnover * 100.,
+        report.raw_turnover * 100.
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_small_changes() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let pool = u16::MAX - config.reserved_weight();
+        let asset = |i: u8, converted_weight: u16| AssetData::test_asset(i, Some(converted_weight));
+        let mut allocation = vec![
+            asset(1, pool / 2 + 10),
+            asset(2, pool / 4 - 10),
+            asset(3, pool - pool / 2 - pool / 4),
+        ];
+        let csv = format!(
+            "oft_address,uint16\n{},{}\n{},{}\n{},{}\n",
+            Address::repeat_byte(1),
+            pool / 2,
+            Address::repeat_byte(2),
+            pool / 4,
+            Address::repeat_byte(4),
+            pool - pool / 2 - pool / 4
+        );
+        let previous = parse_allocation_csv(&csv).unwrap();
+
+        let report = apply_min_change(&mut allocation, &previous, &config, 100).unwrap();
+        // The first two moved by 10 and are kept, the third is new and takes the rest
+        assert_eq!(report.kept.len(), 2);
+        assert_eq!(allocation[0].converted_weight, Some(pool / 2));
+        assert_eq!(allocation[1].converted_weight, Some(pool / 4));
+        assert_eq!(
+            allocation[2].converted_weight,
+            Some(pool - pool / 2 - pool / 4)
+        );
+        assert!(report.turnover < report.raw_turnover);
+    }
+
+    #[test]
+    fn keeps_everything_when_only_noise_moved() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let pool = u16::MAX - config.reserved_weight();
+        let asset = |i: u8, converted_weight: u16| AssetData::test_asset(i, Some(converted_weight));
+        let mut allocation = vec![asset(1, pool / 2 + 30), asset(2, pool - pool / 2 - 30)];
+        let csv = format!(
+            "oft_address,uint16\n{},{}\n{},{}\n",
+            Address::repeat_byte(1),
+            pool / 2,
+            Address::repeat_byte(2),
+            pool - pool / 2
+        );
+        let previous = parse_allocation_csv(&csv).unwrap();
+
+        let report = apply_min_change(&mut allocation, &previous, &config, 100).unwrap();
+        assert_eq!(report.kept.len(), 2);
+        assert_eq!(allocation[0].converted_weight, Some(pool / 2));
+        assert_eq!(allocation[1].converted_weight, Some(pool - pool / 2));
+        assert_eq!(report.turnover, 0.);
+
+        // A previous allocation that no longer fills the pool can't be kept
+        let mut allocation = vec![asset(1, pool / 2 + 30), asset(2, pool - pool / 2 - 30)];
+        let previous = parse_allocation_csv(&csv.replace(
+            &format!(",{}\n", pool - pool / 2),
+            &format!(",{}\n", pool - pool / 2 - 50),
+        ))
+        .unwrap();
+        let report = apply_min_change(&mut allocation, &previous, &config, 100).unwrap();
+        assert!(report.kept.is_empty());
+        assert_eq!(allocation[0].converted_weight, Some(pool / 2 + 30));
+    }
+
+    #[test]
+    fn reads_quoted_fields() {
+        let csv = format!(
+            "# comment\nsymbol, chain ,oft_address,uint16\n\"A,B\",BASE,{},100\n\"say \"\"hi\"\"\",BASE,{},\n",
+            Address::repeat_byte(1),
+            Address::repeat_byte(2)
+        );
+        let previous = parse_allocation_csv(&csv).unwrap();
+        assert_eq!(previous.len(), 1);
+        assert_eq!(previous[0].symbol, "A,B");
+        assert_eq!(previous[0].chain, "BASE");
+        assert_eq!(previous[0].oft_address, Some(Address::repeat_byte(1)));
+        assert_eq!(previous[0].converted_weight, 100);
+        assert!(parse_allocation_csv("symbol,uint16\n").is_err());
+    }
+}
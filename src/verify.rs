@@ -0,0 +1,242 @@
+use crate::{
+    config::{Config, Target},
+    error::Error,
+    fetch_data::MULTICALL_CHUNK,
+    target::{ListMethod, ReadMethod},
+    turnover::PreviousEntry,
+};
+use alloy::{
+    primitives::Address,
+    providers::{
+        MULTICALL3_ADDRESS, Provider, ProviderBuilder,
+        bindings::IMulticall3::{Call3, aggregate3Call},
+    },
+    rpc::types::TransactionRequest,
+    sol_types::SolCall,
+};
+use std::collections::{BTreeSet, HashSet};
+
+/// An expected weight the target contract doesn't hold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub symbol: String,
+    pub oft_address: Address,
+    pub expected: u16,
+    /// `None` when the read reverted
+    pub live: Option<u16>,
+}
+
+/// Result of `verify_allocation`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// Weights read, the expected ones and those of the unexpected assets the contract lists
+    pub checked: usize,
+    pub mismatches: Vec<Mismatch>,
+    /// Whether the contract's own asset list was read. Without it a stale weight left on an
+    /// asset the allocation dropped goes unnoticed.
+    pub listed: bool,
+}
+
+/// Reads the live weight of every address from the target contract with Multicall3
+/// `aggregate3`, `None` for the reads that reverted.
+pub async fn read_live_weights(
+    config: &Config,
+    target: &Target,
+    method: &ReadMethod,
+    addresses: &[Address],
+) -> Result<Vec<Option<u16>>, Error> {
+    let chain = config.chain_name(&target.chain_id);
+    let provider = target_provider(config, target)?;
+
+    let mut weights = Vec::with_capacity(addresses.len());
+    for chunk in addresses.chunks(MULTICALL_CHUNK) {
+        let calls = chunk
+            .iter()
+            .map(|address| {
+                Ok(Call3 {
+                    target: target.address,
+                    allowFailure: true,
+                    callData: method.calldata(*address)?,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        let request = TransactionRequest::default()
+            .to(MULTICALL3_ADDRESS)
+            .input(aggregate3Call { calls }.abi_encode().into());
+        let call = format!("Multicall of {} weights on {}", chunk.len(), target.address);
+        let returned = provider
+            .call(request)
+            .await
+            .map_err(|e| Error::rpc(chain, &call, e))?;
+        let results =
+            aggregate3Call::abi_decode_returns(&returned).map_err(|e| Error::decode(&call, e))?;
+        for (address, result) in chunk.iter().zip(results) {
+            weights.push(match result.success {
+                true => Some(method.decode_weight(&result.returnData).map_err(|e| {
+                    Error::decode(format!("weight of {} on {}", address, chain), e)
+                })?),
+                false => None,
+            });
+        }
+    }
+    Ok(weights)
+}
+
+/// Reads every address the target contract holds a weight for.
+pub async fn read_listed_assets(
+    config: &Config,
+    target: &Target,
+    method: &ListMethod,
+) -> Result<Vec<Address>, Error> {
+    let chain = config.chain_name(&target.chain_id);
+    let request = TransactionRequest::default()
+        .to(target.address)
+        .input(method.calldata().into());
+    let call = format!("{} on {}", method.function.signature(), target.address);
+    let returned = target_provider(config, target)?
+        .call(request)
+        .await
+        .map_err(|e| Error::rpc(chain, &call, e))?;
+    method.decode_addresses(&returned)
+}
+
+fn target_provider(config: &Config, target: &Target) -> Result<impl Provider, Error> {
+    Ok(ProviderBuilder::new().connect_http(config.rpc_endpoint(&target.chain_id)?))
+}
+
+/// Entries expecting a zero weight for the `listed` addresses missing from `expected`.
+pub fn unexpected_entries(
+    expected: &[PreviousEntry],
+    listed: &[Address],
+    chain: &str,
+) -> Vec<PreviousEntry> {
+    let known: HashSet<Address> = expected.iter().filter_map(|x| x.oft_address).collect();
+    listed
+        .iter()
+        .filter(|address| !known.contains(*address))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|address| PreviousEntry {
+            symbol: "unexpected".to_owned(),
+            chain: chain.to_owned(),
+            oft_address: Some(*address),
+            converted_weight: 0,
+        })
+        .collect()
+}
+
+/// Every expected entry whose live weight differs, `live` lined up with `expected`.
+pub fn compare_weights(expected: &[PreviousEntry], live: &[Option<u16>]) -> Vec<Mismatch> {
+    expected
+        .iter()
+        .zip(live)
+        .filter(|(entry, live)| **live != Some(entry.converted_weight))
+        .map(|(entry, live)| Mismatch {
+            symbol: entry.symbol.clone(),
+            oft_address: entry.oft_address.unwrap_or_default(),
+            expected: entry.converted_weight,
+            live: *live,
+        })
+        .collect()
+}
+
+/// Checks that the target contract holds the weight of every expected entry, and with
+/// `list_method` that it holds none on the assets it lists beyond those.
+pub async fn verify_allocation(
+    config: &Config,
+    target: &Target,
+    method: &ReadMethod,
+    list_method: Option<&ListMethod>,
+    expected: &[PreviousEntry],
+) -> Result<Verification, Error> {
+    let mut expected = expected.to_vec();
+    if let Some(list_method) = list_method {
+        let listed = read_listed_assets(config, target, list_method).await?;
+        let chain = config.chain_name(&target.chain_id);
+        expected.extend(unexpected_entries(&expected, &listed, chain));
+    }
+
+    let mut addresses = Vec::with_capacity(expected.len());
+    for entry in expected.iter() {
+        match entry.oft_address {
+            Some(address) => addresses.push(address),
+            None => {
+                return Err(Error::decode(
+                    format!("expected entry {} on {}", entry.symbol, entry.chain),
+                    "no OFT address, the allocation predates the OFT Address column",
+                ));
+            }
+        }
+    }
+    let live = read_live_weights(config, target, method, &addresses).await?;
+    Ok(Verification {
+        checked: expected.len(),
+        mismatches: compare_weights(&expected, &live),
+        listed: list_method.is_some(),
+    })
+}
+
+pub fn print_verification(verification: &Verification) {
+    for mismatch in verification.mismatches.iter() {
+        match mismatch.live {
+            Some(live) => println!(
+                "MISMATCH {:<12} {} expected {:>6}, on-chain {:>6} ({:+})",
+                mismatch.symbol,
+                mismatch.oft_address,
+                mismatch.expected,
+                live,
+                live as i32 - mismatch.expected as i32
+            ),
+            None => println!(
+                "MISMATCH {:<12} {} expected {:>6}, the read reverted",
+                mismatch.symbol, mismatch.oft_address, mismatch.expected
+            ),
+        }
+    }
+    println!(
+        "{} of {} weights match",
+        verification.checked - verification.mismatches.len(),
+        verification.checked
+    );
+    if !verification.listed {
+        println!("No list_method for the [target] contract, unexpected assets were not checked");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_mismatch() {
+        let entry = |i: u8, converted_weight: u16| PreviousEntry {
+            symbol: format!("T{}", i),
+            chain: "BASE".to_owned(),
+            oft_address: Some(Address::repeat_byte(i)),
+            converted_weight,
+        };
+        let expected = vec![entry(1, 30000), entry(2, 20000), entry(3, 15535)];
+        let live = vec![Some(30000), Some(20001), None];
+
+        let mismatches = compare_weights(&expected, &live);
+        assert_eq!(mismatches.len(), 2);
+        assert_eq!(mismatches[0].oft_address, Address::repeat_byte(2));
+        assert_eq!(mismatches[0].live, Some(20001));
+        assert_eq!(mismatches[1].live, None);
+        assert!(compare_weights(&expected, &[Some(30000), Some(20000), Some(15535)]).is_empty());
+
+        // A stale weight on an asset the allocation dropped
+        let listed = [3, 4, 1, 4].map(Address::repeat_byte);
+        let unexpected = unexpected_entries(&expected, &listed, "BASE");
+        assert_eq!(
+            unexpected,
+            vec![PreviousEntry {
+                symbol: "unexpected".to_owned(),
+                ..entry(4, 0)
+            }]
+        );
+        let mismatches = compare_weights(&unexpected, &[Some(12)]);
+        assert_eq!(mismatches[0].live, Some(12));
+        assert!(compare_weights(&unexpected, &[Some(0)]).is_empty());
+    }
+}
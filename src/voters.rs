@@ -0,0 +1,295 @@
+use crate::{
+    AssetData, IJooceVoting, VotingContract,
+    config::Config,
+    error::Error,
+    fetch_data::{
+        MULTICALL_CHUNK, VOTING_CHAIN, collect_chunks, decode_asset_ids, get_asset_ids, get_ticker,
+        pin_block,
+    },
+    u256_division,
+};
+use alloy::{
+    eips::BlockId,
+    primitives::{Address, U256},
+    providers::{DynProvider, Dynamic, MulticallBuilder, Provider},
+};
+use chrono::DateTime;
+use op_alloy_network::Optimism;
+use std::cmp::Reverse;
+
+#[derive(Debug, Clone)]
+pub struct VoterPosition {
+    pub user: Address,
+    /// Share of the user's voting power on this asset, in basis points
+    pub power: U256,
+    /// Timestamp the user's lock, and so this slope, expires
+    pub end: U256,
+    pub last_vote: U256,
+    /// Current bias, `slope * (end - now)`
+    pub weight: U256,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssetVoters {
+    pub asset: AssetData,
+    pub total_weight: U256,
+    /// Sorted by weight, largest first
+    pub voters: Vec<VoterPosition>,
+}
+
+impl AssetVoters {
+    pub fn share(&self, voter: &VoterPosition) -> f64 {
+        u256_division(&voter.weight, &self.total_weight).unwrap_or_default()
+    }
+
+    /// Herfindahl-Hirschman index of the voter shares, 1 means a single voter.
+    pub fn concentration(&self) -> f64 {
+        self.voters.iter().map(|x| self.share(x).powi(2)).sum()
+    }
+}
+
+/// Multicalls `userAssetSlope`, `userVoteAt` and `userPower` for every (voter, asset) pair
+/// and groups the live positions per asset, largest weight first. Every read is made at the
+/// latest block, pinned up front, and weights decay to that block's timestamp.
+pub async fn get_voter_breakdown(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    config: &Config,
+    voters: &[Address],
+) -> Result<(Vec<AssetVoters>, Vec<(Address, U256)>), Error> {
+    let pinned = pin_block(provider, None).await?;
+    let block = pinned.block_id();
+    let asset_ids = get_asset_ids(contract, block).await?;
+    let mut asset_data = decode_asset_ids(&asset_ids, config);
+    let tickers = get_ticker(&asset_data, config).await;
+    for (asset, ticker) in asset_data.iter_mut().zip(tickers) {
+        asset.set_ticker(ticker);
+    }
+
+    let pairs: Vec<(Address, U256)> = voters
+        .iter()
+        .flat_map(|user| asset_ids.iter().map(move |id| (*user, *id)))
+        .collect();
+    let (slopes, last_votes, powers) = tokio::join!(
+        get_user_asset_slopes(provider, contract, &pairs, block),
+        get_user_votes(provider, contract, &pairs, block),
+        get_user_powers(provider, contract, voters, block)
+    );
+    let (slopes, last_votes, powers) = (slopes?, last_votes?, powers?);
+
+    let now = U256::from(pinned.timestamp);
+    let breakdown = group_positions(asset_data, &pairs, slopes, &last_votes, now);
+    Ok((breakdown, voters.iter().copied().zip(powers).collect()))
+}
+
+/// Groups the multicall results of every (voter, asset) pair per asset. The pairs run over
+/// every asset for each voter in turn, so pair `i` is on asset `i % asset_data.len()`.
+/// Positions whose lock has expired by `now` weigh nothing and are left out.
+fn group_positions(
+    asset_data: Vec<AssetData>,
+    pairs: &[(Address, U256)],
+    slopes: Vec<IJooceVoting::userAssetSlopeReturn>,
+    last_votes: &[U256],
+    now: U256,
+) -> Vec<AssetVoters> {
+    let asset_count = asset_data.len();
+    let mut breakdown: Vec<AssetVoters> = asset_data
+        .into_iter()
+        .map(|asset| AssetVoters {
+            asset,
+            total_weight: U256::ZERO,
+            voters: Vec::new(),
+        })
+        .collect();
+
+    for (i, ((user, _id), slope)) in pairs.iter().zip(slopes).enumerate() {
+        if slope.slope.is_zero() {
+            continue;
+        }
+        let weight = slope.slope * slope.end.saturating_sub(now);
+        if weight.is_zero() {
+            continue;
+        }
+        let entry = &mut breakdown[i % asset_count];
+        entry.total_weight += weight;
+        entry.voters.push(VoterPosition {
+            user: *user,
+            power: slope.power,
+            end: slope.end,
+            last_vote: last_votes[i],
+            weight,
+        });
+    }
+    for entry in breakdown.iter_mut() {
+        entry.voters.sort_unstable_by_key(|x| Reverse(x.weight));
+    }
+    breakdown.sort_unstable_by_key(|x| Reverse(x.total_weight));
+    breakdown
+}
+
+async fn get_user_asset_slopes(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    pairs: &[(Address, U256)],
+    block: BlockId,
+) -> Result<Vec<IJooceVoting::userAssetSlopeReturn>, Error> {
+    let chunks = pairs.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::userAssetSlopeCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic().block(block);
+        for (user, id) in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.userAssetSlope(*user, *id));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "userAssetSlope", e))
+    });
+    collect_chunks(chunks).await
+}
+
+async fn get_user_votes(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    pairs: &[(Address, U256)],
+    block: BlockId,
+) -> Result<Vec<U256>, Error> {
+    let chunks = pairs.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::userVoteAtCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic().block(block);
+        for (user, id) in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.userVoteAt(*user, *id));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "userVoteAt", e))
+    });
+    collect_chunks(chunks).await
+}
+
+async fn get_user_powers(
+    provider: &DynProvider<Optimism>,
+    contract: &VotingContract,
+    users: &[Address],
+    block: BlockId,
+) -> Result<Vec<U256>, Error> {
+    let chunks = users.chunks(MULTICALL_CHUNK).map(|chunk| async move {
+        let mut multicall: MulticallBuilder<
+            Dynamic<IJooceVoting::userPowerCall>,
+            &DynProvider<Optimism>,
+            Optimism,
+        > = provider.multicall().dynamic().block(block);
+        for user in chunk.iter() {
+            multicall = multicall.add_dynamic(contract.userPower(*user));
+        }
+        multicall
+            .aggregate()
+            .await
+            .map_err(|e| Error::rpc(VOTING_CHAIN, "userPower", e))
+    });
+    collect_chunks(chunks).await
+}
+
+pub fn print_voter_breakdown(
+    breakdown: &[AssetVoters],
+    powers: &[(Address, U256)],
+    config: &Config,
+    top: usize,
+) {
+    for entry in breakdown.iter().filter(|x| !x.voters.is_empty()) {
+        println!(
+            "{} ({}) - {} voters, HHI {:.3}, asset id {}",
+            entry.asset.report_symbol(),
+            config.chain_name(&entry.asset.chain_id),
+            entry.voters.len(),
+            entry.concentration(),
+            entry.asset.id
+        );
+        for voter in entry.voters.iter().take(top) {
+            println!(
+                "    {} {:>8.4}% power {:>5} bps expires {} last vote {}",
+                voter.user,
+                entry.share(voter) * 100.,
+                voter.power,
+                format_timestamp(voter.end),
+                format_timestamp(voter.last_vote)
+            );
+        }
+    }
+    println!();
+    println!("Voter power used (bps of 10000):");
+    for (user, power) in powers.iter() {
+        println!("    {} {:>5}", user, power);
+    }
+}
+
+fn format_timestamp(timestamp: U256) -> String {
+    if timestamp.is_zero() {
+        return "-".to_owned();
+    }
+    DateTime::from_timestamp(timestamp.saturating_to::<i64>(), 0)
+        .map(|x| x.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| timestamp.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_positions_per_asset() {
+        let assets: Vec<AssetData> = (1..=3).map(|i| AssetData::test_asset(i, None)).collect();
+        let voters = [Address::repeat_byte(0xa), Address::repeat_byte(0xb)];
+        let pairs: Vec<(Address, U256)> = voters
+            .iter()
+            .flat_map(|user| assets.iter().map(move |x| (*user, x.id)))
+            .collect();
+        let now = U256::from(1000);
+        let slope = |slope: u64, end: u64| IJooceVoting::userAssetSlopeReturn {
+            slope: U256::from(slope),
+            power: U256::from(5000),
+            end: U256::from(end),
+        };
+        // Voter a on assets 1 and 3, voter b on assets 1 and 2, a's vote on 3 has expired
+        let slopes = vec![
+            slope(1, 1100),
+            slope(0, 0),
+            slope(7, 900),
+            slope(3, 1100),
+            slope(2, 1050),
+            slope(0, 0),
+        ];
+        let last_votes: Vec<U256> = (0..6).map(U256::from).collect();
+
+        let breakdown = group_positions(assets.clone(), &pairs, slopes, &last_votes, now);
+        assert_eq!(breakdown.len(), 3);
+        // Asset 1: 100 from a and 300 from b, largest first
+        assert_eq!(breakdown[0].asset.id, assets[0].id);
+        assert_eq!(breakdown[0].total_weight, U256::from(400));
+        assert_eq!(breakdown[0].voters[0].user, voters[1]);
+        assert_eq!(breakdown[0].voters[0].last_vote, U256::from(3));
+        assert_eq!(breakdown[0].voters[1].user, voters[0]);
+        // Asset 2: 100 from b
+        assert_eq!(breakdown[1].asset.id, assets[1].id);
+        assert_eq!(breakdown[1].voters.len(), 1);
+        assert_eq!(breakdown[1].voters[0].last_vote, U256::from(4));
+        // Asset 3: a's expired position is not counted as a voter
+        assert_eq!(breakdown[2].asset.id, assets[2].id);
+        assert_eq!(breakdown[2].total_weight, U256::ZERO);
+        assert!(breakdown[2].voters.is_empty());
+
+        assert_eq!(breakdown[0].share(&breakdown[0].voters[0]), 0.75);
+        assert_eq!(
+            breakdown[0].concentration(),
+            0.75f64.powi(2) + 0.25f64.powi(2)
+        );
+        assert_eq!(breakdown[1].concentration(), 1.);
+        assert_eq!(breakdown[2].concentration(), 0.);
+    }
+}
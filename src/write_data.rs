@@ -1,7 +1,10 @@
 use crate::AssetData;
-use crate::variables::CHAIN_ID_TO_STRING;
+use crate::config::Config;
+use crate::error::{Error, env_var};
+use crate::fetch_data::PinnedBlock;
+use crate::turnover::PreviousEntry;
 use alloy::primitives::Address;
-use chrono::Local;
+use chrono::{DateTime, Local};
 use google_sheets4::{
     FieldMask, Sheets,
     api::{
@@ -16,59 +19,280 @@ use google_sheets4::{
     },
     yup_oauth2::{ServiceAccountAuthenticator, read_service_account_key},
 };
-use std::collections::hash_map::HashMap;
+use serde_json::Value;
+use std::{collections::hash_map::HashMap, fmt};
 
-pub async fn write_to_google_sheet(asset_data: &[AssetData]) {
+/// First column of the diff block on a new tab, to the right of the composition.
+const DIFF_COLUMN: &str = "I";
+const DIFF_COLUMN_INDEX: i32 = 8;
+/// How errors name the spreadsheet.
+const SHEET: &str = "the Google Sheet";
+
+/// Google Sheets client used to read and write the allocation tabs.
+pub type SheetsHub = Sheets<HttpsConnector<HttpConnector>>;
+
+/// Authenticates with the service account key in `jooce-cred.json`.
+pub async fn get_sheets_hub() -> Result<SheetsHub, Error> {
     let creds = read_service_account_key("jooce-cred.json")
         .await
-        .expect("Can't read credential, an error occurred");
+        .map_err(|e| Error::Config {
+            message: "Can't read the Google credentials jooce-cred.json".to_owned(),
+            source: Some(e.into()),
+        })?;
     let auth = ServiceAccountAuthenticator::builder(creds)
         .build()
         .await
-        .expect("There was an error, trying to build connection with authenticator");
-
-    let hub = Sheets::new(
-        Client::builder(TokioExecutor::new()).build(
-            HttpsConnectorBuilder::new()
-                .with_native_roots()
-                .unwrap()
-                .https_only()
-                .enable_all_versions()
-                .build(),
-        ),
+        .map_err(|e| Error::sink(SHEET, "Authenticating the service account", e))?;
+    let connector = HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .map_err(|e| Error::sink(SHEET, "Loading the native TLS roots", e))?
+        .https_only()
+        .enable_all_versions()
+        .build();
+
+    Ok(Sheets::new(
+        Client::builder(TokioExecutor::new()).build(connector),
         auth,
+    ))
+}
+
+/// Id of the spreadsheet every tab is read from and written to, `SPREADSHEET_ID`.
+pub fn spreadsheet_id() -> Result<String, Error> {
+    env_var("SPREADSHEET_ID")
+}
+
+/// Turns a Sheets API failure into a sink error naming what was being done.
+pub(crate) fn sheet_error(action: &str) -> impl FnOnce(google_sheets4::Error) -> Error + '_ {
+    move |e| Error::sink(SHEET, action, e)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightChange {
+    pub symbol: String,
+    pub chain: String,
+    pub before: u16,
+    pub after: u16,
+}
+
+impl WeightChange {
+    pub fn delta(&self) -> i32 {
+        self.after as i32 - self.before as i32
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemovalReason {
+    Inactive,
+    /// Relative weight at the time of the run
+    BelowMinimum(f64),
+    /// Still voted on but left without any weight, e.g. by the turnover damping
+    Unallocated,
+    /// Neither in the voting contract nor a reserved slot anymore
+    NotListed,
+}
+
+impl fmt::Display for RemovalReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemovalReason::Inactive => write!(f, "inactive"),
+            RemovalReason::BelowMinimum(weight) => {
+                write!(f, "below minimum ({:.2}% of the vote)", weight * 100.)
+            }
+            RemovalReason::Unallocated => write!(f, "no weight allocated"),
+            RemovalReason::NotListed => write!(f, "no longer listed"),
+        }
+    }
+}
+
+/// Changes between the previous snapshot and a new allocation. Unchanged assets are left out
+/// and `changed` is sorted by the size of the change, largest first.
+#[derive(Debug, Clone, Default)]
+pub struct AllocationDiff {
+    pub added: Vec<WeightChange>,
+    pub removed: Vec<(WeightChange, RemovalReason)>,
+    pub changed: Vec<WeightChange>,
+}
+
+impl AllocationDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares `allocation` to `previous`. `fetched` is the asset data before the filters and
+/// explains why an asset was removed.
+pub fn diff_allocations(
+    allocation: &[AssetData],
+    fetched: &[AssetData],
+    previous: &[PreviousEntry],
+    config: &Config,
+) -> AllocationDiff {
+    let mut diff = AllocationDiff::default();
+    let mut matched = vec![false; previous.len()];
+    for asset in allocation.iter() {
+        let mut change = WeightChange {
+            symbol: asset.symbol.clone().unwrap_or_default(),
+            chain: config.chain_name(&asset.chain_id).to_owned(),
+            before: 0,
+            after: asset.converted_weight.unwrap_or_default(),
+        };
+        match previous.iter().position(|x| x.matches(asset, config)) {
+            Some(i) => {
+                matched[i] = true;
+                change.before = previous[i].converted_weight;
+                if change.delta() != 0 {
+                    diff.changed.push(change);
+                }
+            }
+            None => diff.added.push(change),
+        }
+    }
+
+    for (entry, _) in previous.iter().zip(matched).filter(|(_, x)| !x) {
+        let reason = match fetched.iter().find(|x| entry.matches(x, config)) {
+            Some(asset) if config.inactive_assets.contains(&asset.token_addr) => {
+                RemovalReason::Inactive
+            }
+            Some(asset) => match asset.relative_weight {
+                Some(weight) if weight < config.min_relative_weight => {
+                    RemovalReason::BelowMinimum(weight)
+                }
+                _ => RemovalReason::Unallocated,
+            },
+            None => RemovalReason::NotListed,
+        };
+        let change = WeightChange {
+            symbol: entry.symbol.clone(),
+            chain: entry.chain.clone(),
+            before: entry.converted_weight,
+            after: 0,
+        };
+        diff.removed.push((change, reason));
+    }
+    diff.changed
+        .sort_by_key(|x| (std::cmp::Reverse(x.delta().abs()), x.symbol.clone()));
+    diff
+}
+
+pub fn print_diff(diff: &AllocationDiff) {
+    if diff.is_empty() {
+        println!("No change since the previous snapshot");
+        return;
+    }
+    for change in diff.added.iter() {
+        println!(
+            "+ {:<12} {:<10} {:>6} ({:.2}%)",
+            change.symbol,
+            change.chain,
+            change.after,
+            percentage(change.after) * 100.
+        );
+    }
+    for (change, reason) in diff.removed.iter() {
+        println!(
+            "- {:<12} {:<10} {:>6} ({:.2}%), {}",
+            change.symbol,
+            change.chain,
+            change.before,
+            percentage(change.before) * 100.,
+            reason
+        );
+    }
+    for change in diff.changed.iter() {
+        println!(
+            "~ {:<12} {:<10} {:>6} -> {:>6} ({:+}, {:+.2}%)",
+            change.symbol,
+            change.chain,
+            change.before,
+            change.after,
+            change.delta(),
+            (percentage(change.after) - percentage(change.before)) * 100.
+        );
+    }
+}
+
+fn percentage(converted_weight: u16) -> f64 {
+    converted_weight as f64 / u16::MAX as f64
+}
+
+/// Rows of the diff block written next to the composition of a new tab.
+fn diff_rows(diff: &AllocationDiff) -> Vec<Vec<Value>> {
+    let row = |change: &WeightChange, status: String| {
+        vec![
+            Value::from(change.symbol.as_str()),
+            Value::from(change.chain.as_str()),
+            Value::from(status),
+            Value::from(percentage(change.before)),
+            Value::from(percentage(change.after)),
+            Value::from(percentage(change.after) - percentage(change.before)),
+            Value::from(change.delta()),
+        ]
+    };
+    let mut rows = vec![
+        [
+            "Diff", "Chain", "Status", "Before", "After", "Change", "Uint16",
+        ]
+        .iter()
+        .map(|x| Value::from(*x))
+        .collect(),
+    ];
+    rows.extend(diff.added.iter().map(|x| row(x, "added".to_owned())));
+    rows.extend(
+        diff.removed
+            .iter()
+            .map(|(x, reason)| row(x, format!("removed, {}", reason))),
     );
-    // Get sheet index before we add new sheet
-    let sheet_properties = get_last_sheet_properties(&hub).await;
-    let new_sheet_index = sheet_properties.index.unwrap() + 2;
-    let new_sheet_id = sheet_properties.sheet_id.unwrap() + 1;
+    rows.extend(diff.changed.iter().map(|x| row(x, "changed".to_owned())));
+    rows
+}
 
+/// Appends a tab with the allocation, the pinned block and, when given, the diff against the
+/// previous allocation.
+pub async fn write_to_google_sheet(
+    asset_data: &[AssetData],
+    config: &Config,
+    snapshot_time: DateTime<Local>,
+    block: &PinnedBlock,
+    diff: Option<&AllocationDiff>,
+) -> Result<(), Error> {
     let mut sheet_data = Vec::with_capacity(asset_data.len() + 2);
-    sheet_data.push(vec![
-        serde_json::to_value("Asset").unwrap(),
-        serde_json::to_value("Percentage").unwrap(),
-        serde_json::to_value("Uint16").unwrap(),
-        serde_json::to_value("Chain").unwrap(),
-        serde_json::to_value("").unwrap(),
-    ]);
+    sheet_data.push(
+        ["Asset", "Percentage", "Uint16", "Chain", "OFT Address"]
+            .map(Value::from)
+            .to_vec(),
+    );
 
-    asset_data.iter().for_each(|asset| {
-        let row = vec![
-            serde_json::to_value(asset.symbol.as_ref().unwrap()).unwrap(),
-            serde_json::to_value(asset.actual_weight.unwrap()).unwrap(),
-            serde_json::to_value(asset.converted_weight.unwrap()).unwrap(),
-            serde_json::to_value(CHAIN_ID_TO_STRING.get(&asset.chain_id).unwrap()).unwrap(),
-        ];
-        sheet_data.push(row);
-    });
+    for asset in asset_data.iter() {
+        let converted_weight = asset.allocated_weight()?;
+        sheet_data.push(vec![
+            Value::from(asset.symbol.as_deref().unwrap_or_default()),
+            Value::from(converted_weight as f64 / u16::MAX as f64),
+            Value::from(converted_weight),
+            Value::from(config.chain_name(&asset.chain_id)),
+            Value::from(asset.oft_address.to_checksum(None)),
+        ]);
+    }
 
     let data_length = sheet_data.len();
     sheet_data.push(vec![
-        serde_json::to_value("Checks").unwrap(),
-        serde_json::to_value(format!("=1-SUM(B2:B{})", data_length)).unwrap(),
-        serde_json::to_value(format!("={}-SUM(C2:C{})", u16::MAX, data_length)).unwrap(),
+        Value::from("Checks"),
+        Value::from(format!("=1-SUM(B2:B{})", data_length)),
+        Value::from(format!("={}-SUM(C2:C{})", u16::MAX, data_length)),
     ]);
 
+    let hub = get_sheets_hub().await?;
+    let spreadsheet_id = spreadsheet_id()?;
+    // Get sheet index before we add new sheet
+    let sheet_properties = get_last_sheet_properties(&hub, &spreadsheet_id).await?;
+    let (Some(index), Some(sheet_id)) = (sheet_properties.index, sheet_properties.sheet_id) else {
+        return Err(Error::decode(
+            "the last tab of the Google Sheet",
+            "no index or sheet id",
+        ));
+    };
+    let new_sheet_index = index + 2;
+    let new_sheet_id = sheet_id + 1;
+
     let composition_value_range = ValueRange {
         major_dimension: None,
         range: Some(format!("{}!A1:E50", new_sheet_index)),
@@ -77,76 +301,125 @@ pub async fn write_to_google_sheet(asset_data: &[AssetData]) {
 
     let snapshot_value_range = ValueRange {
         major_dimension: None,
-        range: Some(format!("{}!F1:G1", new_sheet_index)),
-        values: Some(vec![vec![
-            serde_json::to_value("Snapshot Date").unwrap(),
-            serde_json::to_value(format!("{}", Local::now().format("%d/%m/%Y"))).unwrap(),
-        ]]),
+        range: Some(format!("{}!F1:G4", new_sheet_index)),
+        values: Some(vec![
+            vec![
+                Value::from("Snapshot Date"),
+                Value::from(format!("{}", snapshot_time.format("%d/%m/%Y"))),
+            ],
+            vec![Value::from("Block"), Value::from(block.number)],
+            vec![
+                Value::from("Block Hash"),
+                Value::from(block.hash.to_string()),
+            ],
+            vec![
+                Value::from("Block Time"),
+                Value::from(
+                    DateTime::from_timestamp(block.timestamp as i64, 0)
+                        .map(|x| x.to_rfc3339())
+                        .unwrap_or_default(),
+                ),
+            ],
+        ]),
+    };
+    let mut data = vec![snapshot_value_range, composition_value_range];
+    let diff_length = match diff {
+        Some(diff) => {
+            let rows = diff_rows(diff);
+            let diff_length = rows.len();
+            data.push(ValueRange {
+                major_dimension: None,
+                range: Some(format!(
+                    "{}!{}1:O{}",
+                    new_sheet_index, DIFF_COLUMN, diff_length
+                )),
+                values: Some(rows),
+            });
+            diff_length
+        }
+        None => 0,
     };
-    add_sheet_and_set_formatting(&hub, new_sheet_id, new_sheet_index, data_length).await;
+    add_sheet_and_set_formatting(
+        &hub,
+        &spreadsheet_id,
+        new_sheet_id,
+        new_sheet_index,
+        data_length,
+        diff_length,
+    )
+    .await?;
 
-    let _ = hub
-        .spreadsheets()
+    hub.spreadsheets()
         .values_batch_update(
             BatchUpdateValuesRequest {
-                data: Some(vec![snapshot_value_range, composition_value_range]),
+                data: Some(data),
                 value_input_option: Some("USER_ENTERED".to_owned()),
                 ..BatchUpdateValuesRequest::default()
             },
-            std::env::var("SPREADSHEET_ID").unwrap().as_str(),
+            &spreadsheet_id,
         )
         .doit()
-        .await;
+        .await
+        .map_err(sheet_error(&format!(
+            "Writing the allocation to tab {}",
+            new_sheet_index
+        )))?;
+    Ok(())
 }
 
-pub fn print_hashmap(asset_data: &[AssetData]) {
+/// Prints the `{OFT address => u16}` map of the allocation.
+pub fn print_hashmap(asset_data: &[AssetData]) -> Result<(), Error> {
     let mut map: HashMap<String, u16> = HashMap::new();
     map.insert(Address::ZERO.to_checksum(None), 0);
     for i in asset_data {
-        map.insert(i.oft_address.to_checksum(None), i.converted_weight.unwrap());
+        map.insert(i.oft_address.to_checksum(None), i.allocated_weight()?);
     }
-    println!("{:?}", map)
+    println!("{:?}", map);
+    Ok(())
 }
 
-async fn get_last_sheet_properties(hub: &Sheets<HttpsConnector<HttpConnector>>) -> SheetProperties {
+async fn get_last_sheet_properties(
+    hub: &SheetsHub,
+    spreadsheet_id: &str,
+) -> Result<SheetProperties, Error> {
     hub.spreadsheets()
-        .get(std::env::var("SPREADSHEET_ID").unwrap().as_str())
+        .get(spreadsheet_id)
         .doit()
         .await
-        .unwrap()
+        .map_err(sheet_error("Reading the tabs"))?
         .1
         .sheets
-        .unwrap()
-        .last()
-        .unwrap()
-        .properties
-        .clone()
-        .unwrap()
+        .and_then(|sheets| sheets.last()?.properties.clone())
+        .ok_or_else(|| Error::decode("the Google Sheet", "it has no tabs"))
 }
 
 async fn batch_update_request(
-    hub: &Sheets<HttpsConnector<HttpConnector>>,
+    hub: &SheetsHub,
+    spreadsheet_id: &str,
     updates: Option<Vec<Request>>,
-) {
-    let _ = hub
-        .spreadsheets()
+) -> Result<(), Error> {
+    hub.spreadsheets()
         .batch_update(
             BatchUpdateSpreadsheetRequest {
                 requests: updates,
                 ..BatchUpdateSpreadsheetRequest::default()
             },
-            std::env::var("SPREADSHEET_ID").unwrap().as_str(),
+            spreadsheet_id,
         )
         .doit()
-        .await;
+        .await
+        .map_err(sheet_error("Adding and formatting the new tab"))?;
+    Ok(())
 }
 
 async fn add_sheet_and_set_formatting(
-    hub: &Sheets<HttpsConnector<HttpConnector>>,
+    hub: &SheetsHub,
+    spreadsheet_id: &str,
     new_sheet_id: i32,
     new_sheet_index: i32,
     data_length: usize,
-) {
+    diff_length: usize,
+) -> Result<(), Error> {
     // Create struct to add new sheet to spreadsheet
     let add_new_sheet = Request {
         add_sheet: Some(AddSheetRequest {
@@ -305,16 +578,111 @@ async fn add_sheet_and_set_formatting(
         ..Request::default()
     };
 
-    batch_update_request(
-        hub,
-        Some(vec![
-            add_new_sheet,
-            bold_first_row,
-            convert_column_percent,
-            set_snapshot_type,
-            italic_check_row,
-            set_borders,
-        ]),
-    )
-    .await;
+    let mut requests = vec![
+        add_new_sheet,
+        bold_first_row,
+        convert_column_percent,
+        set_snapshot_type,
+        italic_check_row,
+        set_borders,
+    ];
+    if diff_length > 0 {
+        requests.extend(diff_formatting(new_sheet_id, diff_length));
+    }
+    batch_update_request(hub, spreadsheet_id, Some(requests)).await
+}
+
+/// Bold header and percentage columns for the diff block.
+fn diff_formatting(new_sheet_id: i32, diff_length: usize) -> Vec<Request> {
+    let bold_header = Request {
+        repeat_cell: Some(RepeatCellRequest {
+            range: Some(GridRange {
+                sheet_id: Some(new_sheet_id),
+                start_row_index: Some(0),
+                end_row_index: Some(1),
+                start_column_index: Some(DIFF_COLUMN_INDEX),
+                end_column_index: Some(DIFF_COLUMN_INDEX + 7),
+            }),
+            fields: Some(FieldMask::new(&[
+                "userEnteredFormat.textFormat.bold".to_owned()
+            ])),
+            cell: Some(CellData {
+                user_entered_format: Some(CellFormat {
+                    text_format: Some(TextFormat {
+                        bold: Some(true),
+                        ..TextFormat::default()
+                    }),
+                    ..CellFormat::default()
+                }),
+                ..CellData::default()
+            }),
+        }),
+        ..Request::default()
+    };
+    // Before, after and change are shares of the index
+    let percent_columns = Request {
+        repeat_cell: Some(RepeatCellRequest {
+            range: Some(GridRange {
+                sheet_id: Some(new_sheet_id),
+                start_row_index: Some(1),
+                end_row_index: Some(diff_length as i32),
+                start_column_index: Some(DIFF_COLUMN_INDEX + 3),
+                end_column_index: Some(DIFF_COLUMN_INDEX + 6),
+            }),
+            fields: Some(FieldMask::new(&[
+                "userEnteredFormat.numberFormat".to_owned()
+            ])),
+            cell: Some(CellData {
+                user_entered_format: Some(CellFormat {
+                    number_format: Some(NumberFormat {
+                        pattern: Some("0.00%".to_owned()),
+                        type_: Some("PERCENT".to_owned()),
+                    }),
+                    ..CellFormat::default()
+                }),
+                ..CellData::default()
+            }),
+        }),
+        ..Request::default()
+    };
+    vec![bold_header, percent_columns]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_against_previous_snapshot() {
+        let config = Config::from_toml(crate::config::DEFAULT_CONFIG).unwrap();
+        let asset = |i: u8, relative_weight: f64, converted_weight: Option<u16>| AssetData {
+            relative_weight: Some(relative_weight),
+            ..AssetData::test_asset(i, converted_weight)
+        };
+        let entry = |i: u8, converted_weight: u16| PreviousEntry {
+            symbol: format!("T{}", i),
+            chain: "BASE".to_owned(),
+            oft_address: None,
+            converted_weight,
+        };
+        let fetched = vec![asset(1, 0.5, None), asset(2, 0.001, None)];
+        let allocation = vec![
+            asset(1, 0.5, Some(40000)),
+            asset(3, 0.3, Some(100)),
+            asset(4, 0.2, Some(500)),
+        ];
+        let previous = vec![entry(1, 30000), entry(2, 1000), entry(4, 400), entry(5, 10)];
+
+        let diff = diff_allocations(&allocation, &fetched, &previous, &config);
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].symbol, "T3");
+        assert_eq!(
+            diff.removed.iter().map(|x| x.1.clone()).collect::<Vec<_>>(),
+            vec![RemovalReason::BelowMinimum(0.001), RemovalReason::NotListed]
+        );
+        assert_eq!(
+            diff.changed.iter().map(|x| x.delta()).collect::<Vec<_>>(),
+            vec![10000, 100]
+        );
+    }
 }